@@ -0,0 +1,166 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use chrono::Local;
+use reqwest::Client;
+use rosc::OscType;
+use serde_json::json;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+
+use super::{Notification, NOTIFICATIONS};
+use crate::config::{AlertAction, AlertConfig, Config};
+use crate::metrics::{self, Sink};
+use crate::reading::{self, Reading};
+use crate::{osc, template};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Actions {
+  client: Client,
+  osc: Option<(UdpSocket, SocketAddr)>,
+}
+
+impl Actions {
+  pub async fn new(config: &Config) -> anyhow::Result<Self> {
+    let uses_osc = config
+      .alert
+      .iter()
+      .flat_map(|alert| &alert.action)
+      .any(|action| matches!(action, AlertAction::Osc { .. }));
+
+    let osc = if uses_osc {
+      let host: IpAddr = config.osc.host.parse().context("failed to parse osc.host")?;
+      let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+
+      Some((socket, SocketAddr::new(host, config.osc.port)))
+    } else {
+      None
+    };
+
+    Ok(Self {
+      client: Client::builder().timeout(WEBHOOK_TIMEOUT).build()?,
+      osc,
+    })
+  }
+
+  /// Every action of `alert`, failures are logged
+  pub async fn run(&self, alert: &AlertConfig, active: bool) {
+    let reading = reading::get();
+
+    let mut context = template::context(&reading);
+    context.add("alert", alert.name.as_str());
+    context.add("alert_active", active);
+
+    let message = alert.message.render(&context);
+
+    for action in &alert.action {
+      let result = match action {
+        AlertAction::Log => {
+          if active {
+            warn!("alert `{}`: {}", alert.name, message);
+          } else {
+            info!("alert `{}` cleared", alert.name);
+          }
+
+          Ok(())
+        }
+        AlertAction::Command { command } if active => run_command(command, &alert.name, &message, &reading),
+        AlertAction::Command { .. } => Ok(()),
+        AlertAction::Osc { path } => self.send_osc(path, active).await,
+        AlertAction::Webhook {
+          url,
+          body,
+          content_type,
+        } => {
+          let body = match body {
+            Some(body) => body.render(&context),
+            None => json!({
+              "alert": alert.name,
+              "active": active,
+              "message": message,
+              "bpm": reading.as_u8(),
+              "state": reading.state(),
+              "time": crate::rfc3339(&Local::now()),
+            })
+            .to_string(),
+          };
+
+          self.post(url, content_type, body);
+
+          Ok(())
+        }
+        AlertAction::Notify => {
+          let mut notifications = NOTIFICATIONS.lock().unwrap();
+
+          notifications.retain(|notification| notification.alert != alert.name);
+
+          if active {
+            notifications.push(Notification {
+              alert: alert.name.clone(),
+              message: message.clone(),
+              time: Local::now(),
+            });
+          }
+
+          Ok(())
+        }
+      };
+
+      if let Err(e) = result {
+        warn!("alert `{}` action failed: {}", alert.name, e);
+        metrics::error(Sink::Alert);
+      }
+    }
+  }
+
+  async fn send_osc(&self, path: &str, active: bool) -> anyhow::Result<()> {
+    let Some((socket, addr)) = &self.osc else {
+      return Ok(());
+    };
+
+    osc::send(socket, *addr, path, OscType::Bool(active)).await
+  }
+
+  /// In the background so a slow server doesn't hold up other alerts
+  fn post(&self, url: &str, content_type: &str, body: String) {
+    let request = self.client.post(url).header("Content-Type", content_type).body(body);
+
+    let url = url.to_string();
+
+    tokio::spawn(async move {
+      if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+        warn!("alert webhook `{}` failed: {}", url, e);
+        metrics::error(Sink::Alert);
+      }
+    });
+  }
+}
+
+/// Started in the background with the alert, message and reading in
+/// `HRPC_ALERT`, `HRPC_MESSAGE` and `HRPC_READING`, never in the command
+/// itself
+fn run_command(command: &str, alert: &str, message: &str, reading: &Reading) -> anyhow::Result<()> {
+  let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+
+  let mut child = Command::new(shell)
+    .arg(flag)
+    .arg(command)
+    .env("HRPC_ALERT", alert)
+    .env("HRPC_MESSAGE", message)
+    .env("HRPC_READING", reading.as_u8().to_string())
+    .spawn()?;
+
+  let command = command.to_string();
+
+  tokio::spawn(async move {
+    match child.wait().await {
+      Ok(status) if !status.success() => warn!("alert command `{}` exited with {}", command, status),
+      Ok(_) => {}
+      Err(e) => warn!("alert command `{}` failed: {}", command, e),
+    }
+  });
+
+  Ok(())
+}
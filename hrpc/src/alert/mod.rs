@@ -0,0 +1,213 @@
+//! Rules on the template variables that fire actions after holding for a while
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, Instant};
+
+use self::action::Actions;
+use crate::config::{AlertConfig, Config};
+use crate::event::{self, EventKind};
+use crate::metrics::{self, Sink};
+use crate::template::Context;
+use crate::{outbox, reading, shutdown, template};
+
+mod action;
+
+/// Variables added for alert messages and actions, on top of the usual ones
+pub const ALERT_VARIABLES: &[&str] = &["alert", "alert_active"];
+
+/// Rules are checked on every reading, when one is due to fire, and at least
+/// this often
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static NOTIFICATIONS: Mutex<Vec<Notification>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+  pub alert: String,
+  pub message: String,
+  pub time: DateTime<Local>,
+}
+
+/// Alerts with a `notify` action that fired and haven't cleared, oldest first
+pub fn notifications() -> Vec<Notification> {
+  NOTIFICATIONS.lock().unwrap().clone()
+}
+
+pub fn alert_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = alert_task(config).await {
+        error!("alert_task error: {}", e);
+        metrics::error(Sink::Alert);
+      }
+    });
+  })
+}
+
+async fn alert_task(config: Config) -> anyhow::Result<()> {
+  debug!("alert_task start");
+  if config.alert.is_empty() {
+    return Ok(());
+  }
+
+  let actions = Actions::new(&config).await?;
+
+  let mut rules: Vec<Rule> = config.alert.iter().map(Rule::new).collect();
+
+  let mut events = event::subscribe();
+  let mut interval = interval(CHECK_INTERVAL);
+
+  loop {
+    let due = rules.iter().filter_map(Rule::due).min();
+
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(event) if !matches!(event.kind, EventKind::Reading(_) | EventKind::Zone { .. } | EventKind::Profile { .. }) => {
+          continue
+        }
+        Ok(_) | Err(RecvError::Lagged(_)) => {}
+        Err(RecvError::Closed) => break,
+      },
+      _ = interval.tick() => {}
+      _ = outbox::wait(due) => {}
+      _ = shutdown::wait() => break,
+    }
+
+    let context = template::context(&reading::get());
+    let now = Instant::now();
+
+    for rule in &mut rules {
+      if let Some(active) = rule.check(&context, now) {
+        actions.run(rule.config, active).await;
+      }
+    }
+  }
+
+  NOTIFICATIONS.lock().unwrap().clear();
+
+  Ok(())
+}
+
+/// Whether an alert fired, and since when its condition holds
+struct Rule<'a> {
+  config: &'a AlertConfig,
+  since: Option<Instant>,
+  active: bool,
+}
+
+impl<'a> Rule<'a> {
+  fn new(config: &'a AlertConfig) -> Self {
+    Self {
+      config,
+      since: None,
+      active: false,
+    }
+  }
+
+  /// When the alert fires if the condition keeps holding
+  fn due(&self) -> Option<Instant> {
+    self
+      .since
+      .filter(|_| !self.active)
+      .map(|since| since + self.config.duration)
+  }
+
+  /// `Some(true)` when the alert fires, `Some(false)` when it clears
+  fn check(&mut self, context: &Context, now: Instant) -> Option<bool> {
+    if !self.config.when.test(context) {
+      self.since = None;
+
+      if self.active {
+        self.active = false;
+
+        return Some(false);
+      }
+
+      return None;
+    }
+
+    let since = *self.since.get_or_insert(now);
+
+    if self.active || now - since < self.config.duration {
+      return None;
+    }
+
+    self.active = true;
+
+    Some(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::template::Template;
+
+  fn config(duration: u64) -> AlertConfig {
+    AlertConfig {
+      name: "high".to_string(),
+      when: Template::new("{reading > 180}").unwrap(),
+      duration: Duration::from_secs(duration),
+      message: Template::new("").unwrap(),
+      action: Vec::new(),
+    }
+  }
+
+  fn context(reading: i64) -> Context {
+    let mut context = Context::new();
+    context.add("reading", reading);
+
+    context
+  }
+
+  #[test]
+  fn holds_before_firing() {
+    let config = config(10);
+    let mut rule = Rule::new(&config);
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+
+    assert_eq!(rule.check(&context(190), at(0)), None);
+    assert_eq!(rule.due(), Some(at(10)));
+    assert_eq!(rule.check(&context(190), at(9)), None);
+    assert_eq!(rule.check(&context(190), at(10)), Some(true));
+    assert_eq!(rule.due(), None);
+    assert_eq!(rule.check(&context(190), at(20)), None);
+  }
+
+  #[test]
+  fn interruption_restarts_hold() {
+    let config = config(10);
+    let mut rule = Rule::new(&config);
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+
+    rule.check(&context(190), at(0));
+
+    assert_eq!(rule.check(&context(170), at(5)), None);
+    assert_eq!(rule.due(), None);
+    assert_eq!(rule.check(&context(190), at(6)), None);
+    assert_eq!(rule.check(&context(190), at(12)), None);
+    assert_eq!(rule.check(&context(190), at(16)), Some(true));
+  }
+
+  #[test]
+  fn clears_and_fires_again() {
+    let config = config(0);
+    let mut rule = Rule::new(&config);
+    let now = Instant::now();
+
+    assert_eq!(rule.check(&context(190), now), Some(true));
+    assert_eq!(rule.check(&context(190), now), None);
+    assert_eq!(rule.check(&context(170), now), Some(false));
+    assert_eq!(rule.check(&context(170), now), None);
+    assert_eq!(rule.check(&context(190), now), Some(true));
+  }
+}
@@ -1,7 +1,15 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::time::Duration;
 
+use anyhow::{bail, Context};
 use serde::Deserialize;
 
+use crate::alert::ALERT_VARIABLES;
+use crate::influx::has_line_break;
+use crate::logging::{check_retained, SUMMARY_VARIABLES};
+use crate::template::{Template, VARIABLES};
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
   #[serde(deserialize_with = "from_millis")]
@@ -13,10 +21,36 @@ pub struct Config {
   #[serde(deserialize_with = "from_millis")]
   pub scan_timeout: Duration,
   pub monitor: MonitorConfig,
+  #[serde(default)]
+  pub session: SessionConfig,
+  #[serde(default)]
+  pub zones: ZonesConfig,
   pub rpc: RpcConfig,
   pub osc: OscConfig,
   pub log: LogConfig,
-  pub file: FileConfig,
+  /// `[[file]]` entries, a single `[file]` table is also accepted
+  #[serde(default, deserialize_with = "one_or_many")]
+  pub file: Vec<FileConfig>,
+  #[serde(default)]
+  pub store: StoreConfig,
+  #[serde(default)]
+  pub server: ServerConfig,
+  #[serde(default)]
+  pub mqtt: MqttConfig,
+  #[serde(default)]
+  pub influx: InfluxConfig,
+  /// `[[webhook]]` entries, a single `[webhook]` table is also accepted
+  #[serde(default, deserialize_with = "one_or_many")]
+  pub webhook: Vec<WebhookConfig>,
+  #[serde(default)]
+  pub obs: ObsConfig,
+  /// `[[alert]]` rules
+  #[serde(default, deserialize_with = "one_or_many")]
+  pub alert: Vec<AlertConfig>,
+  /// names switched between through the websocket api, the first is active
+  /// on start
+  #[serde(default)]
+  pub profiles: Vec<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -26,23 +60,148 @@ pub struct MonitorConfig {
   pub freeze_timeout: Option<Duration>,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SessionConfig {
+  #[serde(deserialize_with = "from_millis")]
+  pub average_window: Duration,
+  /// rolling averages, `avg(seconds)` in templates
+  #[serde(deserialize_with = "from_millis_list")]
+  pub windows: Vec<Duration>,
+  /// readings the trend is fitted to
+  #[serde(deserialize_with = "from_millis")]
+  pub trend_window: Duration,
+  /// bpm per minute the trend has to exceed to be rising or falling
+  pub trend_threshold: f64,
+}
+
+impl Default for SessionConfig {
+  fn default() -> Self {
+    Self {
+      average_window: Duration::from_secs(60),
+      windows: vec![Duration::from_secs(60), Duration::from_secs(300)],
+      trend_window: Duration::from_secs(30),
+      trend_threshold: 3.0,
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ZonesConfig {
+  /// for zones given in percent
+  pub max_hr: Option<u8>,
+  /// bpm a reading has to be past the bounds of the current zone to leave it
+  pub hysteresis: u8,
+  /// `[[zones.zone]]` entries by ascending lower bound
+  #[serde(deserialize_with = "one_or_many")]
+  pub zone: Vec<ZoneConfig>,
+}
+
+impl Default for ZonesConfig {
+  fn default() -> Self {
+    Self {
+      max_hr: None,
+      hysteresis: 2,
+      zone: Vec::new(),
+    }
+  }
+}
+
+impl ZonesConfig {
+  /// Lower bound of each zone in bpm, a zone ends where the next one starts
+  pub fn bounds(&self) -> Vec<u8> {
+    self
+      .zone
+      .iter()
+      .map(|zone| match (zone.min, zone.min_percent, self.max_hr) {
+        (Some(min), ..) => min,
+        (None, Some(percent), Some(max_hr)) => (max_hr as f32 * percent / 100.0).round() as u8,
+        _ => 0,
+      })
+      .collect()
+  }
+}
+
+/// Starts at either `min` bpm or `min_percent` of `max_hr`
+#[derive(Deserialize, Clone, Debug)]
+pub struct ZoneConfig {
+  pub name: String,
+  pub min: Option<u8>,
+  pub min_percent: Option<f32>,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct RpcConfig {
   pub enable: bool,
   pub id: String,
   #[serde(deserialize_with = "from_millis")]
   pub update_interval: Duration,
+  /// lower bound between any two updates, discord allows 5 per 20s
+  #[serde(default = "default_min_interval", deserialize_with = "from_millis")]
+  pub min_interval: Duration,
+  #[serde(default)]
+  pub disconnected: DisconnectedPolicy,
+  /// time disconnected before clearing with [`DisconnectedPolicy::Clear`]
+  #[serde(default = "default_disconnected_grace", deserialize_with = "from_millis")]
+  pub disconnected_grace: Duration,
+  /// first retry delay when discord isn't reachable, doubled on every failure
+  #[serde(default = "default_reconnect_delay", deserialize_with = "from_millis")]
+  pub reconnect_delay: Duration,
+  #[serde(default = "default_reconnect_delay_max", deserialize_with = "from_millis")]
+  pub reconnect_delay_max: Duration,
+  /// show time elapsed since the session started
+  #[serde(default)]
+  pub elapsed: bool,
   pub templates: RpcTemplates,
+  /// at most 2
+  #[serde(default)]
+  pub buttons: Vec<RpcButton>,
+}
+
+/// Presence while the sensor is disconnected
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectedPolicy {
+  /// render templates as usual
+  #[default]
+  Placeholder,
+  /// clear presence after `disconnected_grace`
+  Clear,
+  /// last reading as frozen, with time elapsed since it was received
+  LastSeen,
+}
+
+fn default_disconnected_grace() -> Duration {
+  Duration::from_secs(60)
+}
+
+fn default_min_interval() -> Duration {
+  Duration::from_secs(4)
+}
+
+fn default_reconnect_delay() -> Duration {
+  Duration::from_secs(2)
+}
+
+fn default_reconnect_delay_max() -> Duration {
+  Duration::from_secs(60)
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct RpcTemplates {
-  pub details: String,
-  pub state: String,
-  pub frozen_details: String,
-  pub frozen_state: String,
-  pub disconnected_details: String,
-  pub disconnected_state: String,
+  pub details: Template,
+  pub state: Template,
+  pub large_image: Option<Template>,
+  pub large_text: Option<Template>,
+  pub small_image: Option<Template>,
+  pub small_text: Option<Template>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RpcButton {
+  pub label: Template,
+  pub url: String,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -54,23 +213,172 @@ pub struct OscConfig {
   pub update_interval: Duration,
   pub percent_min: u8,
   pub percent_max: u8,
+  /// encoding of `floatHR`
+  #[serde(default = "default_float_encoding")]
+  pub float: FloatEncoding,
+  /// encoding of `percentHR`, linear from `percent_min` to `percent_max` if
+  /// unset
+  pub percent: Option<FloatEncoding>,
+  #[serde(default)]
+  pub interpolation: OscInterpolationConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FloatEncoding {
+  #[serde(flatten)]
+  pub curve: FloatCurve,
+  /// value sent while there is no reading
+  #[serde(default)]
+  pub none: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "curve", rename_all = "snake_case")]
+pub enum FloatCurve {
+  Linear(FloatRange),
+  Logarithmic(FloatRange),
+  /// `[input, output]` points sorted by input, linearly interpolated between
+  Table {
+    table: Vec<(f32, f32)>,
+  },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FloatRange {
+  pub input_min: f32,
+  pub input_max: f32,
+  pub output_min: f32,
+  pub output_max: f32,
+  #[serde(default)]
+  pub clamp: bool,
+}
+
+impl FloatEncoding {
+  pub fn linear(input_min: f32, input_max: f32, output_min: f32, output_max: f32, clamp: bool) -> Self {
+    Self {
+      curve: FloatCurve::Linear(FloatRange {
+        input_min,
+        input_max,
+        output_min,
+        output_max,
+        clamp,
+      }),
+      none: 0.0,
+    }
+  }
+}
+
+/// `reading * 0.0078125 - 1.0`
+fn default_float_encoding() -> FloatEncoding {
+  FloatEncoding::linear(0.0, 256.0, -1.0, 1.0, false)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OscInterpolationConfig {
+  pub enable: bool,
+  #[serde(deserialize_with = "from_millis")]
+  pub update_interval: Duration,
+  /// time constant of the easing towards a new reading
+  #[serde(deserialize_with = "from_millis")]
+  pub smoothing: Duration,
+}
+
+impl Default for OscInterpolationConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      update_interval: Duration::from_millis(40),
+      smoothing: Duration::from_millis(500),
+    }
+  }
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct LogConfig {
   pub enable: bool,
   pub write_zero: bool,
+  #[serde(default)]
+  pub mode: LogMode,
   #[serde(deserialize_with = "from_millis")]
   pub update_interval: Duration,
-  pub path: String,
-  pub templates: LogTemplates,
+  /// buffered lines are written out at least this often and on shutdown
+  #[serde(deserialize_with = "from_millis", default = "default_flush_interval")]
+  pub flush_interval: Duration,
+  /// rendered before every write, e.g. `logs/{date}/{session}.csv`
+  pub path: Template,
+  #[serde(default)]
+  pub format: LogFormat,
+  /// line template for [`LogFormat::Text`]
+  #[serde(default = "default_log_template")]
+  pub template: Template,
+  /// write a summary when a session ends, rendered from `summary_template`
+  /// for text logs and as a `"type": "session"` object for jsonl, csv logs
+  /// get none
+  #[serde(default = "default_true")]
+  pub session_summary: bool,
+  #[serde(default = "default_summary_template")]
+  pub summary_template: Template,
+  #[serde(default)]
+  pub rotate: LogRotateConfig,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct LogTemplates {
-  pub template: String,
-  pub frozen_template: String,
-  pub disconnected_template: String,
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LogRotateConfig {
+  /// bytes
+  pub max_size: Option<u64>,
+  pub period: Option<RotatePeriod>,
+  /// gzip rotated files and files left behind when `path` renders differently
+  pub compress: bool,
+  /// log files matching `path` to keep, the oldest are deleted
+  pub keep: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotatePeriod {
+  Hourly,
+  Daily,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogMode {
+  /// current reading every `update_interval`
+  #[default]
+  Interval,
+  /// every reading received from the sensor, with its own timestamp
+  Samples,
+  /// min, avg and max of the readings during each `update_interval`
+  Summary,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+  /// `template` per line
+  #[default]
+  Text,
+  /// `timestamp,bpm,state,sensor` with a header
+  Csv,
+  /// JSON object per line
+  Jsonl,
+}
+
+fn default_flush_interval() -> Duration {
+  Duration::from_secs(5)
+}
+
+fn default_log_template() -> Template {
+  Template::new("{timestamp} {reading|default:0}").unwrap()
+}
+
+fn default_summary_template() -> Template {
+  Template::new(
+    "{timestamp} session ended after {duration}, min {min} avg {avg|round} max {max}{if zone_times}, \
+     {zone_times}{end}",
+  )
+  .unwrap()
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -78,8 +386,321 @@ pub struct FileConfig {
   pub enable: bool,
   #[serde(deserialize_with = "from_millis")]
   pub update_interval: Duration,
-  pub template: String,
+  pub template: Template,
   pub path: String,
+  /// write to a temporary file and rename over `path`,
+  /// disable for tools that keep the file open
+  #[serde(default = "default_true")]
+  pub atomic: bool,
+  /// while the last value is frozen
+  #[serde(default)]
+  pub frozen: FileStateBehaviour,
+  /// while there is no reading
+  #[serde(default)]
+  pub disconnected: FileStateBehaviour,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStateBehaviour {
+  /// render the template as usual
+  #[default]
+  Render,
+  /// leave the file as it is
+  Keep,
+  /// write an empty file
+  Clear,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StoreConfig {
+  pub enable: bool,
+  /// sqlite database, created if missing
+  pub path: String,
+  /// samples are written in one transaction this often, and on shutdown
+  #[serde(deserialize_with = "from_millis")]
+  pub flush_interval: Duration,
+}
+
+impl Default for StoreConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      path: "history.sqlite".to_string(),
+      flush_interval: Duration::from_secs(5),
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ServerConfig {
+  pub enable: bool,
+  pub address: SocketAddr,
+  /// html file served as the overlay instead of the bundled one, read on
+  /// every request
+  pub overlay: Option<String>,
+  /// prometheus `/metrics`
+  pub metrics: bool,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      address: SocketAddr::from(([127, 0, 0, 1], 8151)),
+      overlay: None,
+      metrics: false,
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MqttConfig {
+  pub enable: bool,
+  pub host: String,
+  pub port: u16,
+  pub client_id: String,
+  pub username: Option<String>,
+  pub password: Option<String>,
+  /// 0, 1 or 2
+  pub qos: u8,
+  /// retain `bpm` and the session statistics, state topics are always retained
+  pub retain: bool,
+  #[serde(deserialize_with = "from_millis")]
+  pub reconnect_delay: Duration,
+  pub topics: MqttTopics,
+  /// publish home assistant discovery configs on connecting
+  pub discovery: bool,
+  pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      host: "localhost".to_string(),
+      port: 1883,
+      client_id: "hrpc".to_string(),
+      username: None,
+      password: None,
+      qos: 0,
+      retain: false,
+      reconnect_delay: Duration::from_secs(5),
+      topics: MqttTopics::default(),
+      discovery: false,
+      discovery_prefix: "homeassistant".to_string(),
+    }
+  }
+}
+
+/// Empty topics aren't published
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MqttTopics {
+  pub bpm: String,
+  /// `connected`, `frozen` or `disconnected`
+  pub state: String,
+  pub sensor: String,
+  pub min: String,
+  pub max: String,
+  pub avg: String,
+  /// `online`, or `offline` as last will and on shutdown
+  pub availability: String,
+}
+
+impl Default for MqttTopics {
+  fn default() -> Self {
+    Self {
+      bpm: "hrpc/bpm".to_string(),
+      state: "hrpc/state".to_string(),
+      sensor: "hrpc/sensor".to_string(),
+      min: "hrpc/min".to_string(),
+      max: "hrpc/max".to_string(),
+      avg: "hrpc/avg".to_string(),
+      availability: "hrpc/availability".to_string(),
+    }
+  }
+}
+
+/// Bounded buffer and retries for http sinks
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DeliveryConfig {
+  /// requests or lines kept while the server is unreachable, the oldest are
+  /// dropped
+  pub buffer: usize,
+  #[serde(deserialize_with = "from_millis")]
+  pub retry_delay: Duration,
+  #[serde(deserialize_with = "from_millis")]
+  pub retry_delay_max: Duration,
+  #[serde(deserialize_with = "from_millis")]
+  pub timeout: Duration,
+}
+
+impl Default for DeliveryConfig {
+  fn default() -> Self {
+    Self {
+      buffer: 1000,
+      retry_delay: Duration::from_secs(1),
+      retry_delay_max: Duration::from_secs(60),
+      timeout: Duration::from_secs(10),
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct InfluxConfig {
+  pub enable: bool,
+  /// write endpoint including the database or org and bucket, timestamps are
+  /// in nanoseconds so leave `precision` unset
+  pub url: String,
+  /// sent as `Authorization: Token <token>`
+  pub token: Option<String>,
+  pub measurement: String,
+  /// added to every line next to `sensor`
+  pub tags: BTreeMap<String, String>,
+  #[serde(deserialize_with = "from_millis")]
+  pub batch_interval: Duration,
+  #[serde(flatten)]
+  pub delivery: DeliveryConfig,
+}
+
+impl Default for InfluxConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      url: "http://localhost:8086/api/v2/write?org=hrpc&bucket=hrpc".to_string(),
+      token: None,
+      measurement: "heart_rate".to_string(),
+      tags: BTreeMap::new(),
+      batch_interval: Duration::from_secs(10),
+      delivery: DeliveryConfig {
+        buffer: 100_000,
+        ..Default::default()
+      },
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhookConfig {
+  pub enable: bool,
+  pub url: String,
+  #[serde(default)]
+  pub on: WebhookTrigger,
+  /// request body
+  pub template: Template,
+  #[serde(default = "default_content_type")]
+  pub content_type: String,
+  #[serde(default)]
+  pub headers: BTreeMap<String, String>,
+  #[serde(flatten)]
+  pub delivery: DeliveryConfig,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookTrigger {
+  /// every reading received
+  Reading,
+  /// changes between connected, frozen and disconnected
+  #[default]
+  State,
+  /// changes of the heart rate zone
+  Zone,
+}
+
+fn default_content_type() -> String {
+  "application/json".to_string()
+}
+
+/// obs-websocket 5 client
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ObsConfig {
+  pub enable: bool,
+  pub address: String,
+  pub password: Option<String>,
+  #[serde(deserialize_with = "from_millis")]
+  pub reconnect_delay: Duration,
+  /// `[[obs.text]]` text sources to update
+  #[serde(deserialize_with = "one_or_many")]
+  pub text: Vec<ObsText>,
+  /// `[[obs.item]]` scene items to show or hide
+  #[serde(deserialize_with = "one_or_many")]
+  pub item: Vec<ObsItem>,
+}
+
+impl Default for ObsConfig {
+  fn default() -> Self {
+    Self {
+      enable: false,
+      address: "ws://127.0.0.1:4455".to_string(),
+      password: None,
+      reconnect_delay: Duration::from_secs(5),
+      text: Vec::new(),
+      item: Vec::new(),
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ObsText {
+  /// input name of the text source
+  pub source: String,
+  pub template: Template,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ObsItem {
+  pub scene: String,
+  pub source: String,
+  /// shown unless rendered empty, `false` or `0`
+  pub visible: Template,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AlertConfig {
+  pub name: String,
+  /// active while rendered not empty, `false` or `0`
+  pub when: Template,
+  /// how long `when` has to hold before the alert fires
+  #[serde(default, rename = "for", deserialize_with = "from_millis")]
+  pub duration: Duration,
+  pub message: Template,
+  /// `[[alert.action]]` entries
+  #[serde(deserialize_with = "one_or_many")]
+  pub action: Vec<AlertAction>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertAction {
+  /// the message as a warning, and a note once cleared
+  Log,
+  /// run through `sh -c`, `cmd /C` on windows, when the alert fires. values
+  /// are only passed as `HRPC_*` environment variables, not templated in
+  Command { command: String },
+  /// `true` to `osc.host` when the alert fires, `false` once cleared
+  Osc { path: String },
+  /// posts `body` when the alert fires and once cleared, a json object with
+  /// the alert, message and reading if unset
+  Webhook {
+    url: String,
+    body: Option<Template>,
+    #[serde(default = "default_content_type")]
+    content_type: String,
+  },
+  /// shown in the gui until cleared
+  Notify,
 }
 
 fn from_millis<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -87,6 +708,28 @@ where D: serde::Deserializer<'de> {
   Ok(Duration::from_millis(Deserialize::deserialize(deserializer)?))
 }
 
+fn from_millis_list<'de, D>(deserializer: D) -> Result<Vec<Duration>, D::Error>
+where D: serde::Deserializer<'de> {
+  let millis: Vec<u64> = Deserialize::deserialize(deserializer)?;
+
+  Ok(millis.into_iter().map(Duration::from_millis).collect())
+}
+
+/// A single table or an array of them. Goes through [`toml::Value`] rather
+/// than an untagged enum, which would hide the error of the entry that failed
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+  D: serde::Deserializer<'de>,
+  T: Deserialize<'de>,
+{
+  let entries = match toml::Value::deserialize(deserializer)? {
+    many @ toml::Value::Array(_) => many.try_into(),
+    one => one.try_into().map(|one| vec![one]),
+  };
+
+  entries.map_err(serde::de::Error::custom)
+}
+
 fn from_millis_optional<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
 where D: serde::Deserializer<'de> {
   let millis = Deserialize::deserialize(deserializer)?;
@@ -114,5 +757,191 @@ pub fn load_config() -> anyhow::Result<Config> {
     Err(err) => return Err(err.into()),
   };
 
-  Ok(toml::from_str(&data)?)
+  let config = toml::from_str(&data)?;
+
+  validate(&config)?;
+
+  Ok(config)
+}
+
+/// Checks templates for unknown variables
+fn validate(config: &Config) -> anyhow::Result<()> {
+  let rpc = &config.rpc.templates;
+  let windows = &config.session.windows;
+
+  let mut templates = vec![
+    ("rpc.templates.details", &rpc.details),
+    ("rpc.templates.state", &rpc.state),
+    ("log.path", &config.log.path),
+  ];
+
+  let optional = [
+    ("rpc.templates.large_image", &rpc.large_image),
+    ("rpc.templates.large_text", &rpc.large_text),
+    ("rpc.templates.small_image", &rpc.small_image),
+    ("rpc.templates.small_text", &rpc.small_text),
+  ];
+
+  templates.extend(
+    optional
+      .into_iter()
+      .filter_map(|(name, template)| Some((name, template.as_ref()?))),
+  );
+
+  templates.extend(
+    config
+      .rpc
+      .buttons
+      .iter()
+      .map(|button| ("rpc.buttons.label", &button.label)),
+  );
+  templates.extend(config.file.iter().map(|file| ("file.template", &file.template)));
+  templates.extend(
+    config
+      .webhook
+      .iter()
+      .map(|webhook| ("webhook.template", &webhook.template)),
+  );
+  templates.extend(config.obs.text.iter().map(|text| ("obs.text.template", &text.template)));
+  templates.extend(config.obs.item.iter().map(|item| ("obs.item.visible", &item.visible)));
+
+  if config.log.rotate.keep == Some(0) {
+    bail!("`log.rotate.keep` must keep at least the current file");
+  }
+
+  if config.log.rotate.keep.is_some() {
+    check_retained(config.log.path.source())?;
+  }
+
+  let influx = &config.influx;
+
+  if has_line_break(&influx.measurement)
+    || influx
+      .tags
+      .iter()
+      .any(|(key, value)| has_line_break(key) || has_line_break(value))
+  {
+    bail!("`influx.measurement` and `influx.tags` can't contain line breaks");
+  }
+
+  if config.mqtt.qos > 2 {
+    bail!("`mqtt.qos` must be 0, 1 or 2");
+  }
+
+  for zone in &config.zones.zone {
+    match (zone.min, zone.min_percent) {
+      (Some(_), None) => {}
+      (None, Some(_)) if config.zones.max_hr.is_some() => {}
+      (None, Some(_)) => bail!("zone `{}` is in percent but `zones.max_hr` is unset", zone.name),
+      _ => bail!("zone `{}` needs exactly one of `min` and `min_percent`", zone.name),
+    }
+  }
+
+  if config.zones.bounds().windows(2).any(|pair| pair[0] >= pair[1]) {
+    bail!("`zones.zone` entries must be sorted by ascending lower bound");
+  }
+
+  let encodings = [
+    ("osc.float", Some(&config.osc.float)),
+    ("osc.percent", config.osc.percent.as_ref()),
+  ];
+
+  for (name, encoding) in encodings {
+    if let Some(FloatCurve::Table { table }) = encoding.map(|encoding| &encoding.curve) {
+      if table.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+        bail!("`{name}.table` points must be sorted by input");
+      }
+    }
+  }
+
+  for (index, name) in config.profiles.iter().enumerate() {
+    if name.is_empty() || config.profiles[..index].contains(name) {
+      bail!("`profiles` must be unique and not empty, got `{name}`");
+    }
+  }
+
+  if config.rpc.buttons.len() > 2 {
+    bail!("discord allows at most 2 `rpc.buttons`");
+  }
+
+  for (name, template) in templates {
+    template
+      .validate(VARIABLES, windows)
+      .with_context(|| format!("invalid `{name}`"))?;
+  }
+
+  let alert_variables = [VARIABLES, ALERT_VARIABLES].concat();
+
+  for alert in &config.alert {
+    let mut templates = vec![("when", &alert.when), ("message", &alert.message)];
+
+    for action in &alert.action {
+      if let AlertAction::Webhook { body: Some(body), .. } = action {
+        templates.push(("action.body", body));
+      }
+    }
+
+    for (name, template) in templates {
+      template
+        .validate(&alert_variables, windows)
+        .with_context(|| format!("invalid `{name}` of alert `{}`", alert.name))?;
+    }
+  }
+
+  let log_variables = match config.log.mode {
+    LogMode::Summary => [VARIABLES, SUMMARY_VARIABLES].concat(),
+    _ => VARIABLES.to_vec(),
+  };
+
+  config
+    .log
+    .template
+    .validate(&log_variables, windows)
+    .context("invalid `log.template`")?;
+  config
+    .log
+    .summary_template
+    .validate(VARIABLES, windows)
+    .context("invalid `log.summary_template`")?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXAMPLE: &str = include_str!("../../config.example.toml");
+
+  fn parse(extra: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(&format!("{EXAMPLE}\n{extra}"))
+  }
+
+  #[test]
+  fn example() {
+    let config = parse("").unwrap();
+
+    validate(&config).unwrap();
+  }
+
+  #[test]
+  fn entry_errors() {
+    let error = parse("[[file]]\npath = \"x.txt\"\ntemplate = \"{reading\"").unwrap_err();
+
+    assert!(error.to_string().contains("template"), "{error}");
+    assert!(!error.to_string().contains("untagged"), "{error}");
+
+    let error = parse("[[alert]]\nname = \"x\"\nwhen = \"{reading >}\"\naction = []").unwrap_err();
+
+    assert!(!error.to_string().contains("untagged"), "{error}");
+  }
+
+  #[test]
+  fn single_table() {
+    let single = EXAMPLE.replace("[[webhook]]", "[webhook]");
+
+    let config: Config = toml::from_str(&single).unwrap();
+
+    assert_eq!(config.webhook.len(), 1);
+  }
 }
@@ -0,0 +1,71 @@
+//! Everything the monitor observes, for sinks that need each sample instead
+//! of polling [`crate::reading::get`]
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Local};
+use tokio::sync::broadcast;
+
+use crate::reading::Reading;
+use crate::zone::Zone;
+
+/// Events a slow subscriber can fall behind by before missing some
+const CAPACITY: usize = 1024;
+
+static EVENTS: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| broadcast::channel(CAPACITY).0);
+
+#[derive(Clone, Debug)]
+pub struct Event {
+  pub time: DateTime<Local>,
+  pub kind: EventKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum EventKind {
+  Connected {
+    sensor: String,
+  },
+  Disconnected,
+  /// every reading set by the monitor, including frozen and none
+  Reading(Reading),
+  /// marked by a user, e.g. through the websocket api
+  Mark {
+    label: Option<String>,
+  },
+  /// the reading moved to another heart rate zone, `None` when disconnected
+  /// or below the first zone
+  Zone {
+    from: Option<Zone>,
+    to: Option<Zone>,
+  },
+  /// switched to another of the configured profiles
+  Profile {
+    name: String,
+  },
+}
+
+impl EventKind {
+  /// `connected`, `disconnected`, `reading`, `mark`, `zone` or `profile`
+  pub fn name(&self) -> &'static str {
+    match self {
+      EventKind::Connected { .. } => "connected",
+      EventKind::Disconnected => "disconnected",
+      EventKind::Reading(_) => "reading",
+      EventKind::Mark { .. } => "mark",
+      EventKind::Zone { .. } => "zone",
+      EventKind::Profile { .. } => "profile",
+    }
+  }
+}
+
+/// Works across runtimes, nothing happens without subscribers
+pub fn emit(kind: EventKind) {
+  let _ = EVENTS.send(Event {
+    time: Local::now(),
+    kind,
+  });
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+  EVENTS.subscribe()
+}
@@ -1,48 +1,80 @@
 use tokio::runtime::Runtime;
+use tokio::task::JoinSet;
 use tokio::time::interval;
 
-use crate::config::Config;
-use crate::template::Template;
-use crate::{overwrite, reading};
+use crate::config::{Config, FileConfig, FileStateBehaviour};
+use crate::metrics::{self, Sink};
+use crate::reading::Reading;
+use crate::writer::OverwriteWriter;
+use crate::{overwrite_atomic, reading, shutdown, template};
 
 pub fn file_thread(config: Config) {
   tokio::task::block_in_place(|| {
     let rt = Runtime::new().unwrap();
 
     rt.block_on(async move {
-      if let Err(e) = file_task(config).await {
-        error!("file_task error: {}", e);
+      tokio::select! {
+        _ = file_tasks(config) => {}
+        _ = shutdown::wait() => {}
       }
     });
   })
 }
 
-async fn file_task(config: Config) -> anyhow::Result<()> {
-  debug!("file_task start");
-  if !config.file.enable {
-    return Ok(());
+/// One task per enabled `[[file]]` entry
+async fn file_tasks(config: Config) {
+  let mut tasks = JoinSet::new();
+
+  for file in config.file.into_iter().filter(|file| file.enable) {
+    tasks.spawn(async move {
+      if let Err(e) = file_task(&file).await {
+        error!("file_task `{}` error: {}", file.path, e);
+        metrics::error(Sink::File);
+      }
+    });
   }
 
-  let mut interval = interval(config.file.update_interval);
+  while tasks.join_next().await.is_some() {}
+}
+
+async fn file_task(file: &FileConfig) -> anyhow::Result<()> {
+  debug!("file_task `{}` start", file.path);
+
+  let mut interval = interval(file.update_interval);
 
-  let mut last_reading = 0;
+  let mut writer = OverwriteWriter::new(&file.path);
+
+  let mut last_written: Option<String> = None;
 
   loop {
     interval.tick().await;
 
-    let reading = reading::get().as_u8();
-    if reading == last_reading {
+    let reading = reading::get();
+
+    let behaviour = match reading {
+      Reading::None => file.disconnected,
+      Reading::Frozen(_) => file.frozen,
+      Reading::Value(_) => FileStateBehaviour::Render,
+    };
+
+    let rendered = match behaviour {
+      FileStateBehaviour::Render => file.template.render(&template::context(&reading)),
+      FileStateBehaviour::Keep => continue,
+      FileStateBehaviour::Clear => String::new(),
+    };
+
+    if last_written.as_ref() == Some(&rendered) {
       continue;
     }
-    last_reading = reading;
-
-    let mut template = Template::new(config.file.template.clone());
-    template.add("reading", reading.to_string());
 
-    let rendered = template.render();
+    debug!("file_task `{}` writing `{}`", file.path, rendered);
 
-    debug!("file_task writing `{}`", rendered);
+    if file.atomic {
+      overwrite_atomic(&file.path, rendered.clone()).await?;
+    } else {
+      writer.write(rendered.as_bytes()).await?;
+    }
 
-    overwrite(&config.file.path, rendered).await?;
+    last_written = Some(rendered);
   }
 }
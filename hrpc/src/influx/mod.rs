@@ -0,0 +1,263 @@
+//! Readings as InfluxDB line protocol, posted in batches
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Local};
+use reqwest::Client;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, timeout};
+
+use crate::config::{Config, InfluxConfig};
+use crate::event::{self, EventKind};
+use crate::metrics::{self, Sink};
+use crate::outbox::Outbox;
+use crate::reading::Reading;
+use crate::{session, shutdown};
+
+/// Lines per request
+const BATCH: usize = 5000;
+
+pub fn influx_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = influx_task(config).await {
+        error!("influx_task error: {}", e);
+        metrics::error(Sink::Influx);
+      }
+    });
+  })
+}
+
+async fn influx_task(config: Config) -> anyhow::Result<()> {
+  debug!("influx_task start");
+  if !config.influx.enable {
+    return Ok(());
+  }
+
+  let config = config.influx;
+
+  let client = Client::builder().timeout(config.delivery.timeout).build()?;
+
+  let mut events = event::subscribe();
+  let mut outbox = Outbox::new(&config.delivery);
+  let mut interval = interval(config.batch_interval);
+
+  loop {
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(event) => {
+          if let EventKind::Reading(Reading::Value(bpm)) = event.kind {
+            let sensor = session::get().map(|session| session.sensor).unwrap_or_default();
+
+            outbox.push(line(&config, &sensor, bpm, event.time));
+          }
+        }
+        Err(RecvError::Lagged(missed)) => warn!("influx_task missed {} events", missed),
+        Err(RecvError::Closed) => break,
+      },
+      _ = interval.tick() => {
+        if outbox.is_due() {
+          flush(&client, &config, &mut outbox).await;
+        }
+      }
+      _ = shutdown::wait() => {
+        // one last attempt for whatever is queued
+        let _ = timeout(config.delivery.timeout, flush(&client, &config, &mut outbox)).await;
+
+        break;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Posts batches until the outbox is empty or a request fails
+async fn flush(client: &Client, config: &InfluxConfig, outbox: &mut Outbox<String>) {
+  while !outbox.is_empty() {
+    let lines: Vec<&str> = outbox.front(BATCH).map(String::as_str).collect();
+    let count = lines.len();
+
+    let mut request = client.post(&config.url).body(lines.join("\n"));
+
+    if let Some(token) = &config.token {
+      request = request.header("Authorization", format!("Token {token}"));
+    }
+
+    match request.send().await.and_then(|response| response.error_for_status()) {
+      Ok(_) => {
+        debug!("influx_task wrote {} lines", count);
+
+        outbox.sent(count);
+      }
+      Err(e) => {
+        let delay = outbox.failed();
+
+        warn!("influx write failed: {}, retrying in {}ms", e, delay.as_millis());
+        metrics::error(Sink::Influx);
+
+        break;
+      }
+    }
+  }
+}
+
+/// `heart_rate,sensor=Polar\ H10,host=desktop bpm=72i 1700000000000000000`
+fn line(config: &InfluxConfig, sensor: &str, bpm: u8, time: DateTime<Local>) -> String {
+  let mut line = escape(&config.measurement, &[',', ' ']);
+
+  // empty tag values are rejected, and line breaks can't be escaped. the
+  // configured tags are checked on load, sensor names come from the device
+  let tags = std::iter::once(("sensor", sensor))
+    .chain(config.tags.iter().map(|(key, value)| (key.as_str(), value.as_str())))
+    .filter(|(key, value)| !key.is_empty() && !value.is_empty() && !has_line_break(value));
+
+  for (key, value) in tags {
+    let _ = write!(
+      line,
+      ",{}={}",
+      escape(key, &[',', '=', ' ']),
+      escape(value, &[',', '=', ' '])
+    );
+  }
+
+  let _ = write!(line, " bpm={bpm}i {}", time.timestamp_nanos_opt().unwrap_or_default());
+
+  line
+}
+
+pub fn has_line_break(text: &str) -> bool {
+  text.contains(['\n', '\r'])
+}
+
+/// Backslashes are escaped along with `special`, so a trailing one can't
+/// escape the separator after it
+fn escape(text: &str, special: &[char]) -> String {
+  let mut escaped = String::with_capacity(text.len());
+
+  for c in text.chars() {
+    if c == '\\' || special.contains(&c) {
+      escaped.push('\\');
+    }
+
+    escaped.push(c);
+  }
+
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+  use crate::config::DeliveryConfig;
+  use crate::outbox::stand_in::StandIn;
+
+  fn config(tags: &[(&str, &str)]) -> InfluxConfig {
+    InfluxConfig {
+      tags: tags
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn escaping() {
+    assert_eq!(escape("a b,c=d", &[',', '=', ' ']), r"a\ b\,c\=d");
+    assert_eq!(escape(r"H10\", &[',', '=', ' ']), r"H10\\");
+    assert_eq!(escape("a=b", &[',', ' ']), "a=b");
+  }
+
+  #[test]
+  fn lines() {
+    let time = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+
+    assert_eq!(
+      line(&config(&[("host", "desk top")]), "Polar H10", 72, time),
+      r"heart_rate,sensor=Polar\ H10,host=desk\ top bpm=72i 1700000000000000000"
+    );
+    assert_eq!(
+      line(&config(&[("empty", "")]), r"H10\", 72, time),
+      r"heart_rate,sensor=H10\\ bpm=72i 1700000000000000000"
+    );
+    assert_eq!(
+      line(&config(&[]), "H10\nbpm=0i 0", 72, time),
+      "heart_rate bpm=72i 1700000000000000000"
+    );
+  }
+
+  fn delivery(buffer: usize) -> InfluxConfig {
+    InfluxConfig {
+      delivery: DeliveryConfig {
+        buffer,
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn batches() {
+    let stand_in = StandIn::start().await;
+    let config = InfluxConfig {
+      url: stand_in.url.clone(),
+      ..delivery(BATCH * 2)
+    };
+
+    let mut outbox = Outbox::new(&config.delivery);
+
+    for line in 0..BATCH + 2 {
+      outbox.push(line.to_string());
+    }
+
+    flush(&Client::new(), &config, &mut outbox).await;
+
+    let batches = stand_in.received();
+
+    assert!(outbox.is_empty());
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].lines().count(), BATCH);
+    assert_eq!(batches[1], format!("{}\n{}", BATCH, BATCH + 1));
+  }
+
+  #[tokio::test]
+  async fn buffers_while_unreachable() {
+    let mut stand_in = StandIn::start().await;
+    let config = InfluxConfig {
+      url: stand_in.url.clone(),
+      ..delivery(3)
+    };
+
+    let client = Client::new();
+    let mut outbox = Outbox::new(&config.delivery);
+
+    stand_in.stop().await;
+
+    for line in ["a", "b"] {
+      outbox.push(line.to_string());
+    }
+
+    flush(&client, &config, &mut outbox).await;
+
+    // kept for the retry, which backs off
+    assert_eq!(outbox.front(BATCH).collect::<Vec<_>>(), ["a", "b"]);
+    assert!(!outbox.is_due());
+
+    // the oldest dropped past the buffer
+    for line in ["c", "d"] {
+      outbox.push(line.to_string());
+    }
+
+    stand_in.restart().await;
+    flush(&client, &config, &mut outbox).await;
+
+    assert!(outbox.is_empty());
+    assert_eq!(stand_in.received(), ["b\nc\nd"]);
+  }
+}
@@ -1,39 +1,61 @@
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
+use std::path::Path;
 
+use anyhow::Context;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serializer;
+
+pub mod alert;
 pub mod config;
+pub mod event;
 pub mod file;
+pub mod influx;
 pub mod logging;
+pub mod metrics;
 pub mod monitor;
+pub mod mqtt;
+pub mod obs;
 pub mod osc;
+pub mod outbox;
+pub mod profile;
 pub mod reading;
 pub mod rpc;
+pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod stats;
+pub mod store;
 pub mod template;
+pub mod webhook;
+pub mod writer;
+pub mod zone;
 
 #[macro_use]
 extern crate log;
 
-pub async fn append(path: &str, data: String) -> anyhow::Result<()> {
-  let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
-
-  file.write_all(data.as_bytes()).await?;
-
-  futures_lite::future::block_on(file.flush())?;
+/// `2024-01-31T18:04:05.123+01:00`, the format of every timestamp sent or
+/// logged
+pub fn rfc3339(time: &DateTime<Local>) -> String {
+  time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
 
-  Ok(())
+/// [`rfc3339`] for `#[serde(serialize_with)]`
+pub fn serialize_rfc3339<S: Serializer>(time: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(&rfc3339(time))
 }
 
-pub async fn overwrite(path: &str, data: String) -> anyhow::Result<()> {
-  let mut file = OpenOptions::new()
-    .create(true)
-    .write(true)
-    .truncate(true)
-    .open(path)
-    .await?;
+/// Writes to a temporary file next to `path` and renames it over `path`,
+/// so readers never see a partially written file
+pub async fn overwrite_atomic(path: &str, data: String) -> anyhow::Result<()> {
+  let path = Path::new(path);
+
+  let file_name = path
+    .file_name()
+    .with_context(|| format!("`{}` is not a file path", path.display()))?;
 
-  file.write_all(data.as_bytes()).await?;
+  let temp = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
 
-  futures_lite::future::block_on(file.flush())?;
+  tokio::fs::write(&temp, data).await?;
+  tokio::fs::rename(&temp, path).await?;
 
   Ok(())
 }
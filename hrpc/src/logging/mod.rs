@@ -1,9 +1,24 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local};
 use tokio::runtime::Runtime;
-use tokio::time::interval;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, interval_at, Instant};
+
+pub use self::record::SUMMARY_VARIABLES;
+use self::record::{Record, SessionRecord, Summary, CSV_HEADER, SUMMARY_CSV_HEADER};
+pub use self::rotate::check_retained;
+use self::rotate::Rotation;
+use crate::config::{Config, LogConfig, LogFormat, LogMode};
+use crate::event::{self, Event, EventKind};
+use crate::metrics::{self, Sink};
+use crate::reading::{self, Reading};
+use crate::session::{self, Session};
+use crate::shutdown;
+use crate::template::{self, Context};
 
-use crate::config::Config;
-use crate::template::Template;
-use crate::{append, reading};
+mod record;
+mod rotate;
 
 pub fn log_thread(config: Config) {
   tokio::task::block_in_place(|| {
@@ -12,6 +27,7 @@ pub fn log_thread(config: Config) {
     rt.block_on(async move {
       if let Err(e) = log_task(config).await {
         error!("log_task error: {}", e);
+        metrics::error(Sink::Log);
       }
     });
   })
@@ -23,36 +39,151 @@ async fn log_task(config: Config) -> anyhow::Result<()> {
     return Ok(());
   }
 
+  let mode = config.log.mode;
+
   let mut interval = interval(config.log.update_interval);
+  let mut flush = interval_at(Instant::now() + config.log.flush_interval, config.log.flush_interval);
+
+  let mut events = event::subscribe();
+  let mut summary = Summary::default();
+  // start of the last session summarized
+  let mut summarized = None;
+
+  let mut log = Log::new(&config.log);
 
   loop {
-    interval.tick().await;
+    tokio::select! {
+      _ = interval.tick(), if mode != LogMode::Samples => {
+        let now = Local::now();
+
+        if mode == LogMode::Summary {
+          let summary = std::mem::take(&mut summary);
+
+          if config.log.write_zero || !summary.is_empty() {
+            log.summary(now, &summary).await?;
+          }
+
+          continue;
+        }
+
+        let reading = reading::get();
+
+        if config.log.write_zero || !reading.is_none() {
+          log.sample(now, &reading).await?;
+        }
+      }
+      event = events.recv() => match event {
+        Ok(Event { time, kind: EventKind::Reading(reading) }) => match mode {
+          LogMode::Samples if config.log.write_zero || !reading.is_none() => log.sample(time, &reading).await?,
+          LogMode::Summary => {
+            if let Reading::Value(value) = reading {
+              summary.add(value);
+            }
+          }
+          _ => {}
+        },
+        Ok(Event { time, kind: EventKind::Disconnected }) if config.log.session_summary => {
+          let ended = session::get().filter(|session| !session.is_active() && session.samples() > 0);
+
+          if let Some(session) = ended.filter(|session| summarized != Some(session.start)) {
+            summarized = Some(session.start);
+
+            log.session(time, &session).await?;
+          }
+        }
+        Ok(_) => {}
+        Err(RecvError::Lagged(missed)) => warn!("log_task fell behind and missed {missed} events"),
+        Err(RecvError::Closed) => return log.close().await,
+      },
+      _ = flush.tick() => log.flush().await?,
+      _ = shutdown::wait() => {
+        debug!("log_task flushing on shutdown");
+        return log.close().await;
+      }
+    }
+  }
+}
 
-    let reading = reading::get();
+/// The rotated log file and line formatting
+struct Log<'a> {
+  config: &'a LogConfig,
+  rotation: Rotation,
+  /// `{session}` in `log.path` before the first session
+  started: DateTime<Local>,
+}
 
-    if !config.log.write_zero && reading.is_none() {
-      continue;
+impl<'a> Log<'a> {
+  fn new(config: &'a LogConfig) -> Self {
+    Self {
+      config,
+      rotation: Rotation::new(),
+      started: Local::now(),
     }
+  }
+
+  async fn sample(&mut self, time: DateTime<Local>, reading: &Reading) -> anyhow::Result<()> {
+    let context = template::context_at(reading, time);
 
-    let template = match reading {
-      reading::Reading::None => config.log.templates.disconnected_template.clone(),
-      reading::Reading::Frozen(_) => config.log.templates.frozen_template.clone(),
-      reading::Reading::Value(_) => config.log.templates.template.clone(),
+    let line = match self.config.format {
+      LogFormat::Text => self.config.template.render(&context),
+      LogFormat::Csv => Record::new(time, reading).csv(),
+      LogFormat::Jsonl => Record::new(time, reading).json()?,
     };
 
-    let mut template = Template::new(template);
-    template.add("reading", reading.as_u8().to_string());
-    template.add("timestamp", timestamp());
+    self.write(context, CSV_HEADER, line).await
+  }
 
-    let rendered = template.render();
+  async fn summary(&mut self, time: DateTime<Local>, summary: &Summary) -> anyhow::Result<()> {
+    let mut context = template::context_at(&reading::get(), time);
+    summary.add_to(&mut context);
 
-    debug!("log_task writing `{rendered}`");
+    let line = match self.config.format {
+      LogFormat::Text => self.config.template.render(&context),
+      LogFormat::Csv => summary.record(time).csv(),
+      LogFormat::Jsonl => summary.record(time).json()?,
+    };
 
-    append(&config.log.path, format!("{rendered}\n")).await?;
+    self.write(context, SUMMARY_CSV_HEADER, line).await
   }
-}
 
-/// `1985-04-12T23:20:50`
-fn timestamp() -> String {
-  chrono::Local::now().format("%Y-%m-%dT%H:%M:%S").to_string()
+  /// Nothing for csv, the summary doesn't fit its columns
+  async fn session(&mut self, time: DateTime<Local>, session: &Session) -> anyhow::Result<()> {
+    let context = template::context_at(&reading::get(), time);
+
+    let line = match self.config.format {
+      LogFormat::Text => self.config.summary_template.render(&context),
+      LogFormat::Csv => return Ok(()),
+      LogFormat::Jsonl => SessionRecord::new(session.summary()).json()?,
+    };
+
+    self.write(context, CSV_HEADER, line).await
+  }
+
+  async fn write(&mut self, mut context: Context, csv_header: &str, line: String) -> anyhow::Result<()> {
+    // rather than a path like `.csv` before the first session
+    if context.get("session").is_empty() {
+      context.add("session", self.started.format("%Y%m%d-%H%M%S").to_string());
+    }
+
+    let path = self.config.path.render(&context);
+    let line = format!("{line}\n");
+
+    let writer = self.rotation.prepare(self.config, Path::new(&path), line.len()).await?;
+
+    if self.config.format == LogFormat::Csv && writer.len().await? == 0 {
+      writer.write(format!("{csv_header}\n").as_bytes()).await?;
+    }
+
+    debug!("log_task writing `{}` to `{path}`", line.trim_end());
+
+    writer.write(line.as_bytes()).await
+  }
+
+  async fn flush(&mut self) -> anyhow::Result<()> {
+    self.rotation.flush().await
+  }
+
+  async fn close(&mut self) -> anyhow::Result<()> {
+    self.rotation.close().await
+  }
 }
@@ -0,0 +1,219 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::reading::Reading;
+use crate::session;
+use crate::stats::SessionSummary;
+use crate::template::Context;
+
+pub const CSV_HEADER: &str = "timestamp,bpm,state,sensor";
+pub const SUMMARY_CSV_HEADER: &str = "timestamp,min,avg,max,samples,sensor";
+
+/// Variables added by [`Summary::add_to`], on top of the usual ones
+pub const SUMMARY_VARIABLES: &[&str] = &["interval_min", "interval_avg", "interval_max", "interval_samples"];
+
+/// One entry of a structured log
+#[derive(Serialize, Debug)]
+pub struct Record {
+  #[serde(serialize_with = "crate::serialize_rfc3339")]
+  pub timestamp: DateTime<Local>,
+  pub bpm: Option<u8>,
+  pub state: &'static str,
+  pub sensor: Option<String>,
+}
+
+impl Record {
+  pub fn new(timestamp: DateTime<Local>, reading: &Reading) -> Self {
+    Self {
+      timestamp,
+      bpm: match reading {
+        Reading::None => None,
+        Reading::Frozen(value) | Reading::Value(value) => Some(*value),
+      },
+      state: reading.state(),
+      sensor: active_sensor(),
+    }
+  }
+
+  /// Row matching [`CSV_HEADER`]
+  pub fn csv(&self) -> String {
+    format!(
+      "{},{},{},{}",
+      crate::rfc3339(&self.timestamp),
+      self.bpm.map(|bpm| bpm.to_string()).unwrap_or_default(),
+      self.state,
+      csv_field(self.sensor.as_deref().unwrap_or_default())
+    )
+  }
+
+  pub fn json(&self) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(self)?)
+  }
+}
+
+/// Connected readings received during one summary interval
+#[derive(Default, Debug)]
+pub struct Summary {
+  min: Option<u8>,
+  max: Option<u8>,
+  sum: u64,
+  samples: u64,
+}
+
+impl Summary {
+  pub fn add(&mut self, value: u8) {
+    self.min = Some(self.min.map_or(value, |min| min.min(value)));
+    self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    self.sum += value as u64;
+    self.samples += 1;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samples == 0
+  }
+
+  /// Rounded to one decimal
+  pub fn average(&self) -> Option<f64> {
+    if self.samples == 0 {
+      return None;
+    }
+
+    Some((self.sum as f64 / self.samples as f64 * 10.0).round() / 10.0)
+  }
+
+  /// `interval_*` template variables
+  pub fn add_to(&self, context: &mut Context) {
+    context.add("interval_min", self.min);
+    context.add("interval_avg", self.average());
+    context.add("interval_max", self.max);
+    context.add("interval_samples", self.samples);
+  }
+
+  pub fn record(&self, timestamp: DateTime<Local>) -> SummaryRecord {
+    SummaryRecord {
+      timestamp,
+      min: self.min,
+      avg: self.average(),
+      max: self.max,
+      samples: self.samples,
+      sensor: active_sensor(),
+    }
+  }
+}
+
+/// One row of the summary log mode
+#[derive(Serialize, Debug)]
+pub struct SummaryRecord {
+  /// end of the interval
+  #[serde(serialize_with = "crate::serialize_rfc3339")]
+  pub timestamp: DateTime<Local>,
+  pub min: Option<u8>,
+  pub avg: Option<f64>,
+  pub max: Option<u8>,
+  pub samples: u64,
+  pub sensor: Option<String>,
+}
+
+impl SummaryRecord {
+  /// Row matching [`SUMMARY_CSV_HEADER`]
+  pub fn csv(&self) -> String {
+    format!(
+      "{},{},{},{},{},{}",
+      crate::rfc3339(&self.timestamp),
+      self.min.map(|min| min.to_string()).unwrap_or_default(),
+      self.avg.map(|avg| avg.to_string()).unwrap_or_default(),
+      self.max.map(|max| max.to_string()).unwrap_or_default(),
+      self.samples,
+      csv_field(self.sensor.as_deref().unwrap_or_default())
+    )
+  }
+
+  pub fn json(&self) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(self)?)
+  }
+}
+
+/// Jsonl line for an ended session, `type` tells it apart from readings
+#[derive(Serialize, Debug)]
+pub struct SessionRecord {
+  #[serde(rename = "type")]
+  pub kind: &'static str,
+  #[serde(flatten)]
+  pub summary: SessionSummary,
+}
+
+impl SessionRecord {
+  pub fn new(summary: SessionSummary) -> Self {
+    Self {
+      kind: "session",
+      summary,
+    }
+  }
+
+  pub fn json(&self) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(self)?)
+  }
+}
+
+fn active_sensor() -> Option<String> {
+  session::get()
+    .filter(|session| session.is_active())
+    .map(|session| session.sensor)
+}
+
+/// Quotes fields containing separators, quotes or newlines
+fn csv_field(field: &str) -> String {
+  if field.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+  use crate::config::SessionConfig;
+  use crate::session::Session;
+
+  fn record(bpm: Option<u8>) -> Record {
+    Record {
+      timestamp: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+      bpm,
+      state: "connected",
+      sensor: Some("Polar H10, \"chest\"".to_string()),
+    }
+  }
+
+  #[test]
+  fn csv_row() {
+    let columns = |row: String| row.split_once(',').unwrap().1.to_string();
+
+    assert_eq!(
+      columns(record(Some(72)).csv()),
+      "72,connected,\"Polar H10, \"\"chest\"\"\""
+    );
+    assert_eq!(columns(record(None).csv()), ",connected,\"Polar H10, \"\"chest\"\"\"");
+  }
+
+  #[test]
+  fn json_fields() {
+    let json: serde_json::Value = serde_json::from_str(&record(Some(72)).json().unwrap()).unwrap();
+
+    assert_eq!(json["bpm"], 72);
+    assert_eq!(json["sensor"], "Polar H10, \"chest\"");
+    assert_eq!(json.as_object().unwrap().len(), 4);
+  }
+
+  #[test]
+  fn session_typed() {
+    let summary = Session::new("H10".to_string(), &SessionConfig::default()).summary();
+    let json: serde_json::Value = serde_json::from_str(&SessionRecord::new(summary).json().unwrap()).unwrap();
+
+    assert_eq!(json["type"], "session");
+    assert_eq!(json["sensor"], "H10");
+    assert_eq!(json["samples"], 0);
+  }
+}
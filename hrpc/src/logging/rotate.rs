@@ -0,0 +1,443 @@
+//! Rotation and retention of log files
+//!
+//! Rotated files are renamed to `<name>.<YYYYmmdd-HHMMSS>` next to the log,
+//! `.gz` is appended when compressed. Retention counts every file matching
+//! `log.path` and their rotated copies, with `{date}` and `{session}` matching
+//! only what they render to, so other files next to the logs are left alone
+
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::bail;
+use chrono::{DateTime, Local};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::{LogConfig, RotatePeriod};
+use crate::writer::AppendWriter;
+
+/// The log file currently written to, rotated as configured
+pub struct Rotation {
+  writer: Option<AppendWriter>,
+  /// when writing to the file started, last modification for existing files
+  opened: DateTime<Local>,
+}
+
+impl Rotation {
+  pub fn new() -> Self {
+    Self {
+      writer: None,
+      opened: Local::now(),
+    }
+  }
+
+  /// Rotates or finishes files as needed before writing `len` bytes to `path`
+  pub async fn prepare(&mut self, config: &LogConfig, path: &Path, len: usize) -> anyhow::Result<&mut AppendWriter> {
+    let rotate = &config.rotate;
+    let now = Local::now();
+
+    let mut changed = false;
+
+    if self.writer.as_ref().map(AppendWriter::path) != Some(path) {
+      if let Some(mut previous) = self.writer.take() {
+        debug!(
+          "log path changed from `{}` to `{}`",
+          previous.path().display(),
+          path.display()
+        );
+
+        previous.close().await?;
+
+        if rotate.compress {
+          compress(previous.path().to_path_buf()).await?;
+        }
+      }
+
+      if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+      }
+
+      self.opened = modified(path).await.unwrap_or(now);
+
+      changed = true;
+    }
+
+    let opened = self.opened;
+    let writer = self.writer.get_or_insert_with(|| AppendWriter::new(path));
+
+    let size = writer.len().await?;
+
+    let oversized = rotate.max_size.is_some_and(|max| size + len as u64 > max);
+    let expired = rotate
+      .period
+      .is_some_and(|period| key(period, opened) != key(period, now));
+
+    if size > 0 && (oversized || expired) {
+      let rotated = rotated_path(path, opened);
+
+      info!("rotating `{}` to `{}`", path.display(), rotated.display());
+
+      writer.close().await?;
+      tokio::fs::rename(path, &rotated).await?;
+
+      if rotate.compress {
+        compress(rotated).await?;
+      }
+
+      self.opened = now;
+
+      changed = true;
+    }
+
+    if let Some(keep) = rotate.keep.filter(|_| changed) {
+      retain(config.path.source(), keep, path).await?;
+    }
+
+    Ok(self.writer.as_mut().unwrap())
+  }
+
+  pub async fn flush(&mut self) -> anyhow::Result<()> {
+    match &mut self.writer {
+      Some(writer) => writer.flush().await,
+      None => Ok(()),
+    }
+  }
+
+  pub async fn close(&mut self) -> anyhow::Result<()> {
+    match &mut self.writer {
+      Some(writer) => writer.close().await,
+      None => Ok(()),
+    }
+  }
+}
+
+/// Equal for times within the same period
+fn key(period: RotatePeriod, time: DateTime<Local>) -> String {
+  match period {
+    RotatePeriod::Hourly => time.format("%Y%m%d%H").to_string(),
+    RotatePeriod::Daily => time.format("%Y%m%d").to_string(),
+  }
+}
+
+async fn modified(path: &Path) -> Option<DateTime<Local>> {
+  let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+
+  Some(modified.into())
+}
+
+/// `<path>.<opened>`, with a counter if taken
+fn rotated_path(path: &Path, opened: DateTime<Local>) -> PathBuf {
+  let stamp = opened.format("%Y%m%d-%H%M%S");
+
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+  let taken = |candidate: &Path| candidate.exists() || with_suffix(candidate, ".gz").exists();
+
+  let mut rotated = path.with_file_name(format!("{name}.{stamp}"));
+  let mut counter = 1;
+
+  while taken(&rotated) {
+    rotated = path.with_file_name(format!("{name}.{stamp}-{counter}"));
+    counter += 1;
+  }
+
+  rotated
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut path = path.as_os_str().to_owned();
+  path.push(suffix);
+
+  PathBuf::from(path)
+}
+
+/// Replaces `path` with `<path>.gz`, nothing if `path` is gone
+async fn compress(path: PathBuf) -> anyhow::Result<()> {
+  tokio::task::spawn_blocking(move || {
+    let Ok(file) = File::open(&path) else {
+      return Ok(());
+    };
+
+    let compressed = with_suffix(&path, ".gz");
+
+    debug!("compressing `{}`", path.display());
+
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&compressed)?), Compression::default());
+
+    io::copy(&mut BufReader::new(file), &mut encoder)?;
+    encoder.finish()?;
+
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+  })
+  .await?
+}
+
+/// Deletes the oldest files matching `template` until `keep` remain,
+/// `current` is always kept
+async fn retain(template: &str, keep: usize, current: &Path) -> anyhow::Result<()> {
+  let components: Vec<Component> = Path::new(template).components().collect();
+
+  let Some(name) = components.len().checked_sub(1) else {
+    return Ok(());
+  };
+
+  // components before the first templated one are fixed, the file name never is
+  let fixed = components[..name]
+    .iter()
+    .take_while(|component| !component.as_os_str().to_string_lossy().contains('{'))
+    .count();
+
+  let root: PathBuf = components[..fixed].iter().collect();
+  let patterns = components[fixed..]
+    .iter()
+    .map(|component| segments(&component.as_os_str().to_string_lossy()))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+
+  let current = current.to_path_buf();
+
+  tokio::task::spawn_blocking(move || {
+    let mut files = Vec::new();
+    collect(&root, &patterns, &mut files);
+
+    files.retain(|(path, _)| *path != current);
+    files.sort_by_key(|(_, modified)| Reverse(*modified));
+
+    for (path, _) in files.into_iter().skip(keep - 1) {
+      info!("deleting old log `{}`", path.display());
+
+      std::fs::remove_file(&path)?;
+
+      // drop directories emptied by per-date or per-session paths
+      let mut parent = path.parent();
+
+      while let Some(dir) = parent.filter(|dir| *dir != root && !dir.as_os_str().is_empty()) {
+        if std::fs::remove_dir(dir).is_err() {
+          break;
+        }
+
+        parent = dir.parent();
+      }
+    }
+
+    Ok(())
+  })
+  .await?
+}
+
+fn collect(dir: &Path, patterns: &[Vec<Segment>], files: &mut Vec<(PathBuf, SystemTime)>) {
+  let Some((pattern, rest)) = patterns.split_first() else {
+    return;
+  };
+
+  let read = if dir.as_os_str().is_empty() {
+    Path::new(".")
+  } else {
+    dir
+  };
+
+  let Ok(entries) = std::fs::read_dir(read) else {
+    return;
+  };
+
+  for entry in entries.flatten() {
+    let name = entry.file_name().to_string_lossy().to_string();
+    let path = dir.join(&name);
+
+    let Ok(metadata) = entry.metadata() else {
+      continue;
+    };
+
+    if !rest.is_empty() {
+      if metadata.is_dir() && matches(pattern, &name) {
+        collect(&path, rest, files);
+      }
+    } else if metadata.is_file() && matches_log(pattern, &name) {
+      files.push((path, metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+    }
+  }
+}
+
+/// Part of a `log.path` component, tags match only the shape they render to
+#[derive(Debug, PartialEq)]
+enum Segment {
+  Literal(String),
+  /// `{date}`, `2024-01-31`
+  Date,
+  /// `{session}`, `20240131-180405`
+  Session,
+}
+
+/// Checks `log.path` only uses tags retention can tell apart from other files
+pub fn check_retained(template: &str) -> anyhow::Result<()> {
+  for component in Path::new(template).components() {
+    segments(&component.as_os_str().to_string_lossy())?;
+  }
+
+  Ok(())
+}
+
+/// Literal text and tags of `pattern`, with `{{` and `}}` unescaped
+fn segments(pattern: &str) -> anyhow::Result<Vec<Segment>> {
+  let mut segments = Vec::new();
+  let mut literal = String::new();
+  let mut chars = pattern.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        literal.push('{');
+      }
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        literal.push('}');
+      }
+      '{' => {
+        let tag: String = chars.by_ref().take_while(|c| *c != '}').collect();
+
+        let segment = match tag.trim() {
+          "date" => Segment::Date,
+          "session" => Segment::Session,
+          tag => bail!("`log.rotate.keep` needs `log.path` to only use {{date}} and {{session}}, not {{{tag}}}"),
+        };
+
+        if !literal.is_empty() {
+          segments.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+
+        segments.push(segment);
+      }
+      c => literal.push(c),
+    }
+  }
+
+  if !literal.is_empty() {
+    segments.push(Segment::Literal(literal));
+  }
+
+  Ok(segments)
+}
+
+/// `name` matches `pattern`, optionally followed by a rotation stamp and `.gz`
+fn matches_log(pattern: &[Segment], name: &str) -> bool {
+  let name = name.strip_suffix(".gz").unwrap_or(name);
+
+  if matches(pattern, name) {
+    return true;
+  }
+
+  let Some((base, stamp)) = name.rsplit_once('.') else {
+    return false;
+  };
+
+  // `<stamp>` or `<stamp>-<counter>` from `rotated_path`
+  let (stamp, counter) = match stamp.char_indices().nth(STAMP.len()) {
+    Some((index, _)) => stamp.split_at(index),
+    None => (stamp, ""),
+  };
+
+  let counter_fits = counter.is_empty()
+    || counter
+      .strip_prefix('-')
+      .is_some_and(|counter| !counter.is_empty() && counter.chars().all(|c| c.is_ascii_digit()));
+
+  fits(STAMP, stamp) && counter_fits && matches(pattern, base)
+}
+
+/// Shape of a rotation stamp and of `{session}`, `0` for any digit
+const STAMP: &str = "00000000-000000";
+/// Shape of `{date}`
+const DATE: &str = "0000-00-00";
+
+fn matches(pattern: &[Segment], name: &str) -> bool {
+  let Some((segment, rest)) = pattern.split_first() else {
+    return name.is_empty();
+  };
+
+  let shape = match segment {
+    Segment::Literal(literal) => {
+      return name
+        .strip_prefix(literal.as_str())
+        .is_some_and(|name| matches(rest, name));
+    }
+    Segment::Date => DATE,
+    Segment::Session => STAMP,
+  };
+
+  name.get(..shape.len()).is_some_and(|head| fits(shape, head)) && matches(rest, &name[shape.len()..])
+}
+
+/// `text` has a digit wherever `shape` has `0` and is the same elsewhere
+fn fits(shape: &str, text: &str) -> bool {
+  shape.len() == text.len()
+    && shape
+      .chars()
+      .zip(text.chars())
+      .all(|(shape, c)| if shape == '0' { c.is_ascii_digit() } else { shape == c })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn log(pattern: &str, name: &str) -> bool {
+    matches_log(&segments(pattern).unwrap(), name)
+  }
+
+  #[test]
+  fn tag_shapes() {
+    assert!(log("{date}.txt", "2024-01-31.txt"));
+    assert!(log("{date}.txt", "2024-01-31.txt.20240131-180405"));
+    assert!(log("{date}.txt", "2024-01-31.txt.20240131-180405-2.gz"));
+    assert!(log("hr-{session}.csv", "hr-20240131-180405.csv"));
+    assert!(log("log.txt", "log.txt.20240131-180405"));
+
+    assert!(!log("{date}.txt", "rate.txt"));
+    assert!(!log("{date}.txt", "min_max.txt"));
+    assert!(!log("{date}.txt", "2024-01-31.txt.old"));
+    assert!(!log("{date}.txt", "2024-01-31.txt.20240131-180405-"));
+    assert!(!log("{session}.csv", ".csv"));
+    assert!(!log("{session}.csv", "20240131.csv"));
+    assert!(!log("log.txt", "log.txt.bak"));
+    assert!(!log("{date}.txt", "2024-01-3é.txt.2024013é-180405"));
+  }
+
+  #[test]
+  fn other_tags() {
+    assert!(check_retained("logs/{date}/{session}.csv").is_ok());
+    assert!(check_retained("{sensor}.txt").is_err());
+    assert!(check_retained("{date|upper}.txt").is_err());
+  }
+
+  #[tokio::test]
+  async fn absolute_paths() {
+    let dir = std::env::temp_dir().join(format!("hrpc-retain-{}", std::process::id()));
+    let day = |day: u32| dir.join(format!("2024-01-{day:02}.txt"));
+
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for path in [day(1), day(2), day(3), dir.join("rate.txt"), dir.join("min_max.txt")] {
+      std::fs::write(&path, "").unwrap();
+      std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let template = format!("{}/{{date}}.txt", dir.display());
+
+    retain(&template, 2, &day(3)).await.unwrap();
+
+    let exists = |path: PathBuf| path.exists();
+
+    assert!(!exists(day(1)));
+    assert!(exists(day(2)));
+    assert!(exists(day(3)));
+    assert!(exists(dir.join("rate.txt")));
+    assert!(exists(dir.join("min_max.txt")));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,12 +1,20 @@
 use std::{env, thread};
 
 use anyhow::Context;
+use hrpc::alert::alert_thread;
 use hrpc::config::load_config;
 use hrpc::file::file_thread;
+use hrpc::influx::influx_thread;
 use hrpc::logging::log_thread;
 use hrpc::monitor::monitor_thread;
+use hrpc::mqtt::mqtt_thread;
+use hrpc::obs::obs_thread;
 use hrpc::osc::osc_thread;
 use hrpc::rpc::rpc_thread;
+use hrpc::server::server_thread;
+use hrpc::store::store_thread;
+use hrpc::webhook::webhook_thread;
+use hrpc::{profile, shutdown};
 use log::info;
 
 fn main() -> anyhow::Result<()> {
@@ -20,6 +28,8 @@ fn main() -> anyhow::Result<()> {
 
   let config = load_config().context("failed to load config from `config.toml`")?;
 
+  profile::init(&config.profiles);
+
   let osc_config = config.clone();
   let osc = thread::spawn(move || osc_thread(osc_config));
 
@@ -32,13 +42,61 @@ fn main() -> anyhow::Result<()> {
   let log_config = config.clone();
   let log = thread::spawn(move || log_thread(log_config));
 
-  let monitor = thread::spawn(move || monitor_thread(config));
+  let store_config = config.clone();
+  let store = thread::spawn(move || store_thread(store_config));
+
+  let server_config = config.clone();
+  let server = thread::spawn(move || server_thread(server_config));
+
+  let mqtt_config = config.clone();
+  let mqtt = thread::spawn(move || mqtt_thread(mqtt_config));
+
+  let influx_config = config.clone();
+  let influx = thread::spawn(move || influx_thread(influx_config));
+
+  let webhook_config = config.clone();
+  let webhook = thread::spawn(move || webhook_thread(webhook_config));
+
+  let obs_config = config.clone();
+  let obs = thread::spawn(move || obs_thread(obs_config));
+
+  let alert_config = config.clone();
+  let alert = thread::spawn(move || alert_thread(alert_config));
+
+  let monitor = thread::spawn(move || {
+    let result = monitor_thread(config);
+    // nothing feeds the other threads without the monitor
+    shutdown::trigger();
+    result
+  });
+
+  tokio::runtime::Runtime::new()?.block_on(async {
+    tokio::select! {
+      result = tokio::signal::ctrl_c() => result,
+      _ = shutdown::wait() => Ok(()),
+    }
+  })?;
+
+  info!("shutting down");
+
+  shutdown::trigger();
 
-  monitor.join().unwrap()?;
   osc.join().unwrap();
   rpc.join().unwrap();
   file.join().unwrap();
   log.join().unwrap();
+  store.join().unwrap();
+  server.join().unwrap();
+  mqtt.join().unwrap();
+  influx.join().unwrap();
+  webhook.join().unwrap();
+  obs.join().unwrap();
+  alert.join().unwrap();
+
+  // the monitor doesn't stop on shutdown, it only returns when it failed
+  if monitor.is_finished() {
+    monitor.join().unwrap()?;
+  }
 
   Ok(())
 }
@@ -0,0 +1,162 @@
+//! Counters and gauges served on `/metrics` in the Prometheus text format
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::reading::{self, Reading};
+use crate::session;
+
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static DISCONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static OSC_PACKETS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: [AtomicU64; Sink::ALL.len()] = [const { AtomicU64::new(0) }; Sink::ALL.len()];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sink {
+  Monitor,
+  Osc,
+  Rpc,
+  File,
+  Log,
+  Store,
+  Server,
+  Mqtt,
+  Influx,
+  Webhook,
+  Obs,
+  Alert,
+}
+
+impl Sink {
+  const ALL: [Sink; 12] = [
+    Sink::Monitor,
+    Sink::Osc,
+    Sink::Rpc,
+    Sink::File,
+    Sink::Log,
+    Sink::Store,
+    Sink::Server,
+    Sink::Mqtt,
+    Sink::Influx,
+    Sink::Webhook,
+    Sink::Obs,
+    Sink::Alert,
+  ];
+
+  fn name(&self) -> &'static str {
+    match self {
+      Sink::Monitor => "monitor",
+      Sink::Osc => "osc",
+      Sink::Rpc => "rpc",
+      Sink::File => "file",
+      Sink::Log => "log",
+      Sink::Store => "store",
+      Sink::Server => "server",
+      Sink::Mqtt => "mqtt",
+      Sink::Influx => "influx",
+      Sink::Webhook => "webhook",
+      Sink::Obs => "obs",
+      Sink::Alert => "alert",
+    }
+  }
+}
+
+pub fn connected() {
+  CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn disconnected() {
+  DISCONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn osc_packet() {
+  OSC_PACKETS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn error(sink: Sink) {
+  ERRORS[sink as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn render() -> String {
+  let mut out = String::new();
+
+  let reading = reading::get();
+
+  if let Reading::Frozen(value) | Reading::Value(value) = reading {
+    metric(
+      &mut out,
+      "hrpc_bpm",
+      "gauge",
+      "Current heart rate, absent while disconnected",
+    );
+    let _ = writeln!(out, "hrpc_bpm {value}");
+  }
+
+  metric(&mut out, "hrpc_state", "gauge", "1 for the current reading state");
+  for state in ["connected", "frozen", "disconnected"] {
+    let _ = writeln!(
+      out,
+      "hrpc_state{{state=\"{state}\"}} {}",
+      (reading.state() == state) as u8
+    );
+  }
+
+  let uptime = session::get()
+    .filter(|session| session.is_active())
+    .map(|session| session.duration().as_secs())
+    .unwrap_or(0);
+
+  metric(
+    &mut out,
+    "hrpc_connection_uptime_seconds",
+    "gauge",
+    "Time connected to the current sensor",
+  );
+  let _ = writeln!(out, "hrpc_connection_uptime_seconds {uptime}");
+
+  counter(
+    &mut out,
+    "hrpc_sensor_connections_total",
+    "Sensor connections",
+    &CONNECTIONS,
+  );
+  counter(
+    &mut out,
+    "hrpc_sensor_disconnections_total",
+    "Sensor connections lost or dropped",
+    &DISCONNECTIONS,
+  );
+  counter(
+    &mut out,
+    "hrpc_osc_packets_sent_total",
+    "OSC packets sent",
+    &OSC_PACKETS,
+  );
+
+  metric(
+    &mut out,
+    "hrpc_sink_errors_total",
+    "counter",
+    "Errors per sink, including lost connections",
+  );
+  for sink in Sink::ALL {
+    let _ = writeln!(
+      out,
+      "hrpc_sink_errors_total{{sink=\"{}\"}} {}",
+      sink.name(),
+      ERRORS[sink as usize].load(Ordering::Relaxed)
+    );
+  }
+
+  out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(out, "# HELP {name} {help}");
+  let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+  metric(out, name, "counter", help);
+  let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
@@ -1,13 +1,25 @@
+use std::sync::LazyLock;
 use std::time::Instant;
 
 use anyhow::bail;
 use blehr::{Scanner, Sensor};
 use futures_lite::StreamExt;
 use tokio::runtime::Runtime;
+use tokio::sync::Notify;
 use tokio::time::{sleep, timeout};
 
 use crate::config::Config;
+use crate::event::{self, EventKind};
+use crate::metrics::{self, Sink};
 use crate::reading::{self, Reading};
+use crate::{session, zone};
+
+static RECONNECT: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// Drops the sensor connection and scans again
+pub fn reconnect() {
+  RECONNECT.notify_waiters();
+}
 
 pub fn monitor_thread(config: Config) -> anyhow::Result<()> {
   tokio::task::block_in_place(|| {
@@ -23,11 +35,19 @@ async fn monitor_loop(config: &Config) {
   loop {
     if let Err(e) = monitor_task(config).await {
       error!("{:?}", e);
+      metrics::error(Sink::Monitor);
 
       sleep(config.restart_delay).await;
     }
 
-    reading::set(Reading::None);
+    publish(config, Reading::None);
+
+    if let Some(session) = session::end() {
+      info!("session ended: {}", session.summary());
+      metrics::disconnected();
+    }
+
+    event::emit(EventKind::Disconnected);
   }
 }
 
@@ -38,16 +58,26 @@ async fn monitor_task(config: &Config) -> anyhow::Result<()> {
 
   let mut stream = sensor.hr_stream().await?;
 
-  info!(
-    "connected to sensor: {}",
-    sensor.name().await.unwrap_or_else(|| "unknown name".to_string())
-  );
+  let name = sensor.name().await.unwrap_or_else(|| "unknown name".to_string());
+
+  info!("connected to sensor: {name}");
+
+  session::start(name.clone(), &config.session);
+  event::emit(EventKind::Connected { sensor: name });
+  metrics::connected();
 
   let mut last_reading_time = Instant::now();
   let mut freeze_time: Option<Instant> = None;
 
   loop {
-    let reading = timeout(config.read_timeout, stream.next()).await?;
+    let reading = tokio::select! {
+      reading = timeout(config.read_timeout, stream.next()) => reading?,
+      _ = RECONNECT.notified() => {
+        info!("reconnecting to sensor");
+
+        return Ok(());
+      }
+    };
 
     let reading = match reading {
       Some(reading) => reading,
@@ -70,27 +100,42 @@ async fn monitor_task(config: &Config) -> anyhow::Result<()> {
         freeze_time = None;
 
         if let Some(value) = reading {
-          reading::set(Reading::Value(value));
+          publish(config, Reading::Value(value));
         } else if let Reading::Value(value) = reading::get() {
-          reading::set(Reading::Frozen(value))
+          publish(config, Reading::Frozen(value))
         }
       } else if freeze_time.is_none() {
         freeze_time = Some(Instant::now());
       } else if let Some(timeout) = config.monitor.freeze_timeout {
         if freeze_time.unwrap().elapsed() > timeout {
-          reading::set(Reading::None);
+          publish(config, Reading::None);
         }
       }
     } else if let Some(value) = reading {
-      reading::set(Reading::Value(value));
+      publish(config, Reading::Value(value));
     } else {
-      reading::set(Reading::None);
+      publish(config, Reading::None);
     }
 
     last_reading_time = Instant::now();
   }
 }
 
+/// Sets the current reading, session and zone and emits them as events
+fn publish(config: &Config, reading: Reading) {
+  reading::set(reading);
+
+  // before the event so its subscribers see the session with this reading
+  if let Reading::Value(value) = reading {
+    session::record(value);
+  }
+
+  // after the session, which counts the time since the previous reading
+  // towards the zone it was in, and before the reading event
+  zone::update(&config.zones, reading);
+  event::emit(EventKind::Reading(reading));
+}
+
 async fn find_sensor(config: &Config) -> anyhow::Result<Sensor> {
   debug!("scanning for sensors");
 
@@ -0,0 +1,125 @@
+//! Home Assistant MQTT discovery, one sensor entity per published topic
+
+use serde_json::json;
+
+use crate::config::MqttConfig;
+
+/// `(topic, retained payload)` for every non-empty topic
+pub fn configs(config: &MqttConfig) -> Vec<(String, String)> {
+  let topics = &config.topics;
+
+  let node = node_id(&config.client_id);
+
+  let entities = [
+    ("bpm", "Heart rate", &topics.bpm, Some("bpm"), "mdi:heart-pulse"),
+    (
+      "min",
+      "Session minimum",
+      &topics.min,
+      Some("bpm"),
+      "mdi:arrow-collapse-down",
+    ),
+    (
+      "max",
+      "Session maximum",
+      &topics.max,
+      Some("bpm"),
+      "mdi:arrow-collapse-up",
+    ),
+    ("avg", "Session average", &topics.avg, Some("bpm"), "mdi:chart-line"),
+    ("state", "Sensor state", &topics.state, None, "mdi:bluetooth"),
+    ("sensor", "Sensor", &topics.sensor, None, "mdi:watch"),
+  ];
+
+  entities
+    .into_iter()
+    .filter(|(_, _, topic, ..)| !topic.is_empty())
+    .map(|(key, name, topic, unit, icon)| {
+      let mut payload = json!({
+        "name": name,
+        "unique_id": format!("{node}_{key}"),
+        "state_topic": topic,
+        "icon": icon,
+        "device": {
+          "identifiers": [node],
+          "name": "hrpc",
+          "model": "heart rate monitor",
+        },
+      });
+
+      if let Some(unit) = unit {
+        payload["unit_of_measurement"] = json!(unit);
+        payload["state_class"] = json!("measurement");
+      }
+
+      if !topics.availability.is_empty() {
+        payload["availability_topic"] = json!(topics.availability);
+      }
+
+      (
+        format!("{}/sensor/{node}/{key}/config", config.discovery_prefix),
+        payload.to_string(),
+      )
+    })
+    .collect()
+}
+
+/// Letters, digits, `_` and `-` are allowed in discovery topics
+fn node_id(client_id: &str) -> String {
+  client_id
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::Value;
+
+  use super::*;
+
+  #[test]
+  fn node_ids() {
+    assert_eq!(node_id("hrpc"), "hrpc");
+    assert_eq!(node_id("my hrpc.1/a-b"), "my_hrpc_1_a-b");
+  }
+
+  #[test]
+  fn entities() {
+    let mut config = MqttConfig {
+      client_id: "desk top".to_string(),
+      ..MqttConfig::default()
+    };
+    config.topics.sensor = String::new();
+
+    let configs = configs(&config);
+
+    let topics: Vec<&str> = configs.iter().map(|(topic, _)| topic.as_str()).collect();
+
+    assert_eq!(
+      topics,
+      [
+        "homeassistant/sensor/desk_top/bpm/config",
+        "homeassistant/sensor/desk_top/min/config",
+        "homeassistant/sensor/desk_top/max/config",
+        "homeassistant/sensor/desk_top/avg/config",
+        "homeassistant/sensor/desk_top/state/config",
+      ]
+    );
+
+    let payload = |index: usize| serde_json::from_str::<Value>(&configs[index].1).unwrap();
+
+    let bpm = payload(0);
+
+    assert_eq!(bpm["unique_id"], "desk_top_bpm");
+    assert_eq!(bpm["state_topic"], "hrpc/bpm");
+    assert_eq!(bpm["unit_of_measurement"], "bpm");
+    assert_eq!(bpm["availability_topic"], "hrpc/availability");
+    assert_eq!(bpm["device"]["identifiers"][0], "desk_top");
+
+    let state = payload(4);
+
+    assert!(state.get("unit_of_measurement").is_none());
+    assert!(state.get("state_class").is_none());
+  }
+}
@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event as MqttEvent, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout, Instant};
+
+use crate::config::{Config, MqttConfig};
+use crate::event::{self, Event, EventKind};
+use crate::metrics::{self, Sink};
+use crate::reading::Reading;
+use crate::session::Session;
+use crate::{outbox, reading, session, shutdown};
+
+mod discovery;
+
+/// Requests queued while the broker is unreachable before publishes are
+/// dropped
+const QUEUE: usize = 64;
+
+/// How long shutdown waits for `offline` to be sent
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn mqtt_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = mqtt_task(config).await {
+        error!("mqtt_task error: {}", e);
+        metrics::error(Sink::Mqtt);
+      }
+    });
+  })
+}
+
+async fn mqtt_task(config: Config) -> anyhow::Result<()> {
+  debug!("mqtt_task start");
+  if !config.mqtt.enable {
+    return Ok(());
+  }
+
+  let config = config.mqtt;
+
+  let (client, mut eventloop) = AsyncClient::new(options(&config), QUEUE);
+
+  let mut events = event::subscribe();
+  let mut publisher = Publisher::new(&client, &config);
+  // the connection isn't polled until then after an error
+  let mut retry: Option<Instant> = None;
+
+  loop {
+    tokio::select! {
+      notification = eventloop.poll(), if retry.is_none() => match notification {
+        Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+          info!("connected to mqtt broker {}:{}", config.host, config.port);
+
+          publisher.announce(&reading::get(), session::get().as_ref());
+        }
+        Ok(_) => {}
+        Err(e) => {
+          warn!("mqtt connection error: {}, retrying in {}ms", e, config.reconnect_delay.as_millis());
+          metrics::error(Sink::Mqtt);
+
+          retry = Some(Instant::now() + config.reconnect_delay);
+        }
+      },
+      _ = outbox::wait(retry) => retry = None,
+      event = events.recv() => match event {
+        Ok(event) => publisher.event(&event),
+        Err(RecvError::Lagged(_)) => publisher.current(),
+        Err(RecvError::Closed) => break,
+      },
+      _ = shutdown::wait() => break,
+    }
+  }
+
+  debug!("mqtt_task disconnecting");
+
+  publisher.publish(&config.topics.availability, true, "offline");
+
+  let _ = client.try_disconnect();
+
+  // drive the connection until the disconnect is sent
+  let _ = timeout(DISCONNECT_TIMEOUT, async {
+    loop {
+      match eventloop.poll().await {
+        Ok(MqttEvent::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+        Ok(_) => {}
+      }
+    }
+  })
+  .await;
+
+  Ok(())
+}
+
+fn options(config: &MqttConfig) -> MqttOptions {
+  let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+
+  if let Some(username) = &config.username {
+    options.set_credentials(username, config.password.clone().unwrap_or_default());
+  }
+
+  if !config.topics.availability.is_empty() {
+    options.set_last_will(LastWill::new(
+      &config.topics.availability,
+      "offline",
+      qos(config.qos),
+      true,
+    ));
+  }
+
+  options
+}
+
+fn qos(qos: u8) -> QoS {
+  match qos {
+    0 => QoS::AtMostOnce,
+    1 => QoS::AtLeastOnce,
+    _ => QoS::ExactlyOnce,
+  }
+}
+
+/// Publishes values that changed since they were last published
+struct Publisher<'a> {
+  client: &'a AsyncClient,
+  config: &'a MqttConfig,
+  bpm: Option<u8>,
+  state: Option<&'static str>,
+  sensor: Option<String>,
+  min: Option<u8>,
+  max: Option<u8>,
+  avg: Option<String>,
+}
+
+impl<'a> Publisher<'a> {
+  fn new(client: &'a AsyncClient, config: &'a MqttConfig) -> Self {
+    Self {
+      client,
+      config,
+      bpm: None,
+      state: None,
+      sensor: None,
+      min: None,
+      max: None,
+      avg: None,
+    }
+  }
+
+  /// After (re)connecting, availability, discovery and every value again
+  fn announce(&mut self, reading: &Reading, session: Option<&Session>) {
+    self.publish(&self.config.topics.availability, true, "online");
+
+    if self.config.discovery {
+      for (topic, payload) in discovery::configs(self.config) {
+        self.publish(&topic, true, payload);
+      }
+    }
+
+    *self = Self::new(self.client, self.config);
+
+    self.reading(reading, session);
+  }
+
+  fn event(&mut self, event: &Event) {
+    match &event.kind {
+      EventKind::Reading(reading) => self.reading(reading, session::get().as_ref()),
+      EventKind::Connected { .. } | EventKind::Disconnected => self.current(),
+      _ => {}
+    }
+  }
+
+  fn current(&mut self) {
+    self.reading(&reading::get(), session::get().as_ref());
+  }
+
+  fn reading(&mut self, reading: &Reading, session: Option<&Session>) {
+    let topics = &self.config.topics;
+    let retain = self.config.retain;
+
+    if self.state != Some(reading.state()) {
+      self.state = Some(reading.state());
+      self.publish(&topics.state, true, reading.state());
+    }
+
+    if let Reading::Frozen(value) | Reading::Value(value) = *reading {
+      if self.bpm != Some(value) {
+        self.bpm = Some(value);
+        self.publish(&topics.bpm, retain, value.to_string());
+      }
+    }
+
+    let Some(session) = session else {
+      return;
+    };
+
+    if self.sensor.as_ref() != Some(&session.sensor) {
+      self.publish(&topics.sensor, true, session.sensor.clone());
+      self.sensor = Some(session.sensor.clone());
+    }
+
+    if session.min.is_some() && self.min != session.min {
+      self.min = session.min;
+      self.publish(&topics.min, retain, session.min.unwrap_or_default().to_string());
+    }
+
+    if session.max.is_some() && self.max != session.max {
+      self.max = session.max;
+      self.publish(&topics.max, retain, session.max.unwrap_or_default().to_string());
+    }
+
+    let avg = session.average().map(|avg| format!("{avg:.1}"));
+
+    if avg.is_some() && self.avg != avg {
+      self.publish(&topics.avg, retain, avg.clone().unwrap_or_default());
+      self.avg = avg;
+    }
+  }
+
+  /// Dropped if the topic is empty or too many requests are queued
+  fn publish(&self, topic: &str, retain: bool, payload: impl Into<Vec<u8>>) {
+    if topic.is_empty() {
+      return;
+    }
+
+    if let Err(e) = self.client.try_publish(topic, qos(self.config.qos), retain, payload) {
+      debug!("mqtt publish to `{}` dropped: {}", topic, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rumqttc::Request;
+
+  use super::*;
+  use crate::config::SessionConfig;
+
+  /// `(topic, payload)` of the publishes queued since the last call
+  fn published(requests: &flume::Receiver<Request>) -> Vec<(String, String)> {
+    requests
+      .try_iter()
+      .filter_map(|request| match request {
+        Request::Publish(publish) => Some((publish.topic, String::from_utf8_lossy(&publish.payload).to_string())),
+        _ => None,
+      })
+      .collect()
+  }
+
+  fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+      .iter()
+      .map(|(topic, payload)| (topic.to_string(), payload.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn publishes_changes() {
+    let (sender, requests) = flume::unbounded();
+    let client = AsyncClient::from_senders(sender);
+
+    let mut config = MqttConfig::default();
+    config.topics.sensor = String::new();
+
+    let mut publisher = Publisher::new(&client, &config);
+
+    let mut session = Session::new("H10".to_string(), &SessionConfig::default());
+    session.record(70, std::time::Instant::now(), None);
+    publisher.reading(&Reading::Value(70), Some(&session));
+
+    assert_eq!(
+      published(&requests),
+      pairs(&[
+        ("hrpc/state", "connected"),
+        ("hrpc/bpm", "70"),
+        ("hrpc/min", "70"),
+        ("hrpc/max", "70"),
+        ("hrpc/avg", "70.0"),
+      ])
+    );
+
+    publisher.reading(&Reading::Value(70), Some(&session));
+
+    assert_eq!(published(&requests), vec![]);
+
+    session.record(80, std::time::Instant::now(), None);
+    publisher.reading(&Reading::Value(80), Some(&session));
+
+    assert_eq!(
+      published(&requests),
+      pairs(&[("hrpc/bpm", "80"), ("hrpc/max", "80"), ("hrpc/avg", "75.0")])
+    );
+
+    publisher.reading(&Reading::Frozen(80), Some(&session));
+
+    assert_eq!(published(&requests), pairs(&[("hrpc/state", "frozen")]));
+
+    publisher.reading(&Reading::None, Some(&session));
+
+    assert_eq!(published(&requests), pairs(&[("hrpc/state", "disconnected")]));
+
+    // everything again after reconnecting
+    publisher.announce(&Reading::Value(80), Some(&session));
+
+    assert_eq!(
+      published(&requests),
+      pairs(&[
+        ("hrpc/availability", "online"),
+        ("hrpc/state", "connected"),
+        ("hrpc/bpm", "80"),
+        ("hrpc/min", "70"),
+        ("hrpc/max", "80"),
+        ("hrpc/avg", "75.0"),
+      ])
+    );
+  }
+}
@@ -0,0 +1,179 @@
+//! The parts of the obs-websocket 5 protocol needed to send requests
+
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const RPC_VERSION: u64 = 1;
+
+const HELLO: u64 = 0;
+const IDENTIFY: u64 = 1;
+const IDENTIFIED: u64 = 2;
+const REQUEST: u64 = 6;
+const REQUEST_RESPONSE: u64 = 7;
+
+/// For the handshake and every request, obs is treated as gone after it
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An identified connection, requests are sent one at a time
+pub struct Obs {
+  socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+  next_id: u64,
+}
+
+impl Obs {
+  /// Connects and identifies without subscribing to any obs events
+  pub async fn connect(address: &str, password: Option<&str>) -> anyhow::Result<Self> {
+    timeout(TIMEOUT, Self::identify(address, password))
+      .await
+      .context("obs didn't identify in time")?
+  }
+
+  async fn identify(address: &str, password: Option<&str>) -> anyhow::Result<Self> {
+    let (socket, _) = connect_async(address).await?;
+
+    let mut obs = Self { socket, next_id: 0 };
+
+    let hello = obs.receive(HELLO).await?;
+
+    let mut identify = json!({ "rpcVersion": RPC_VERSION, "eventSubscriptions": 0 });
+
+    if let Some(auth) = hello.get("authentication") {
+      let password = password.context("obs requires a password, set `obs.password`")?;
+
+      identify["authentication"] = authentication(
+        password,
+        auth["salt"].as_str().unwrap_or_default(),
+        auth["challenge"].as_str().unwrap_or_default(),
+      )
+      .into();
+    }
+
+    obs.send(IDENTIFY, identify).await?;
+    obs.receive(IDENTIFIED).await?;
+
+    Ok(obs)
+  }
+
+  /// Errors if the connection fails, the inner result is the `responseData`
+  /// or the comment obs gave for rejecting the request
+  pub async fn request(&mut self, kind: &str, data: Value) -> anyhow::Result<Result<Value, String>> {
+    timeout(TIMEOUT, self.respond(kind, data))
+      .await
+      .with_context(|| format!("no response to {kind} in time"))?
+  }
+
+  async fn respond(&mut self, kind: &str, data: Value) -> anyhow::Result<Result<Value, String>> {
+    self.next_id += 1;
+
+    let id = self.next_id.to_string();
+
+    self
+      .send(
+        REQUEST,
+        json!({ "requestType": kind, "requestId": id, "requestData": data }),
+      )
+      .await?;
+
+    loop {
+      let mut response = self.receive(REQUEST_RESPONSE).await?;
+
+      if response["requestId"] != id.as_str() {
+        continue;
+      }
+
+      let status = &response["requestStatus"];
+
+      if status["result"] == true {
+        return Ok(Ok(response["responseData"].take()));
+      }
+
+      return Ok(Err(format!(
+        "{} ({})",
+        status["comment"].as_str().unwrap_or("no comment"),
+        status["code"]
+      )));
+    }
+  }
+
+  /// Reads until the connection fails, for while no request is pending
+  pub async fn closed(&mut self) -> anyhow::Error {
+    loop {
+      if let Err(e) = self.message().await {
+        return e;
+      }
+    }
+  }
+
+  pub async fn close(&mut self) {
+    let _ = self.socket.close(None).await;
+  }
+
+  async fn send(&mut self, op: u64, data: Value) -> anyhow::Result<()> {
+    let message = json!({ "op": op, "d": data });
+
+    self.socket.send(Message::Text(message.to_string())).await?;
+
+    Ok(())
+  }
+
+  /// `d` of the next message with `op`, skipping others
+  async fn receive(&mut self, op: u64) -> anyhow::Result<Value> {
+    loop {
+      let mut message = self.message().await?;
+
+      if message["op"] == op {
+        return Ok(message["d"].take());
+      }
+    }
+  }
+
+  async fn message(&mut self) -> anyhow::Result<Value> {
+    loop {
+      match self.socket.next().await {
+        Some(Ok(Message::Text(text))) => return Ok(serde_json::from_str(&text)?),
+        Some(Ok(Message::Close(Some(frame)))) => {
+          bail!("closed by obs: {} ({})", frame.reason, u16::from(frame.code))
+        }
+        Some(Ok(Message::Close(None))) | None => bail!("closed by obs"),
+        // pings are answered by tungstenite
+        Some(Ok(_)) => {}
+        Some(Err(e)) => return Err(e.into()),
+      }
+    }
+  }
+}
+
+/// `base64(sha256(base64(sha256(password + salt)) + challenge))`
+fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+  let secret = BASE64.encode(Sha256::digest(format!("{password}{salt}")));
+
+  BASE64.encode(Sha256::digest(format!("{secret}{challenge}")))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The example from the obs-websocket protocol docs
+  #[test]
+  fn authentication_string() {
+    assert_eq!(
+      authentication(
+        "supersecretpassword",
+        "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=",
+        "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY="
+      ),
+      "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+    );
+  }
+}
@@ -0,0 +1,298 @@
+//! Updates text sources and scene items through obs-websocket
+
+use serde_json::json;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::sleep;
+
+use self::client::Obs;
+use crate::config::{Config, ObsConfig};
+use crate::event::{self, EventKind};
+use crate::metrics::{self, Sink};
+use crate::reading::{self, Reading};
+use crate::{shutdown, template};
+
+mod client;
+
+pub fn obs_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = obs_task(config).await {
+        error!("obs_task error: {}", e);
+        metrics::error(Sink::Obs);
+      }
+    });
+  })
+}
+
+async fn obs_task(config: Config) -> anyhow::Result<()> {
+  debug!("obs_task start");
+  if !config.obs.enable {
+    return Ok(());
+  }
+
+  let config = config.obs;
+
+  loop {
+    match connection(&config).await {
+      Ok(()) => break,
+      Err(e) => {
+        warn!(
+          "obs connection error: {}, retrying in {}ms",
+          e,
+          config.reconnect_delay.as_millis()
+        );
+        metrics::error(Sink::Obs);
+      }
+    }
+
+    tokio::select! {
+      _ = sleep(config.reconnect_delay) => {}
+      _ = shutdown::wait() => break,
+    }
+  }
+
+  Ok(())
+}
+
+/// Until the connection fails, `Ok` on shutdown
+async fn connection(config: &ObsConfig) -> anyhow::Result<()> {
+  let mut events = event::subscribe();
+
+  let mut obs = tokio::select! {
+    obs = Obs::connect(&config.address, config.password.as_deref()) => obs?,
+    _ = shutdown::wait() => return Ok(()),
+  };
+
+  info!("connected to obs at {}", config.address);
+
+  let mut scene = Scene::new(config, &mut obs).await?;
+
+  scene.update(&mut obs, &reading::get()).await?;
+
+  loop {
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(event) => match event.kind {
+          EventKind::Reading(reading) => scene.update(&mut obs, &reading).await?,
+          EventKind::Connected { .. } | EventKind::Disconnected | EventKind::Profile { .. } => {
+            scene.update(&mut obs, &reading::get()).await?
+          }
+          _ => {}
+        },
+        Err(RecvError::Lagged(_)) => scene.update(&mut obs, &reading::get()).await?,
+        Err(RecvError::Closed) => return Ok(()),
+      },
+      e = obs.closed() => return Err(e),
+      _ = shutdown::wait() => {
+        obs.close().await;
+
+        return Ok(());
+      }
+    }
+  }
+}
+
+/// What was last sent to obs, to only send changes
+struct Scene<'a> {
+  config: &'a ObsConfig,
+  /// per `[[obs.text]]`
+  text: Vec<Option<String>>,
+  /// the last update of the text source failed, to warn once
+  text_failed: Vec<bool>,
+  /// per `[[obs.item]]`, `None` for items missing from the scene
+  item_ids: Vec<Option<i64>>,
+  visible: Vec<Option<bool>>,
+}
+
+impl<'a> Scene<'a> {
+  /// Looks up the scene items
+  async fn new(config: &'a ObsConfig, obs: &mut Obs) -> anyhow::Result<Self> {
+    let mut item_ids = Vec::with_capacity(config.item.len());
+
+    for item in &config.item {
+      let response = obs
+        .request(
+          "GetSceneItemId",
+          json!({ "sceneName": item.scene, "sourceName": item.source }),
+        )
+        .await?;
+
+      item_ids.push(match response {
+        Ok(data) => data["sceneItemId"].as_i64(),
+        Err(e) => {
+          warn!("obs scene item `{}` in `{}` not found: {}", item.source, item.scene, e);
+
+          None
+        }
+      });
+    }
+
+    Ok(Self {
+      config,
+      text: vec![None; config.text.len()],
+      text_failed: vec![false; config.text.len()],
+      item_ids,
+      visible: vec![None; config.item.len()],
+    })
+  }
+
+  async fn update(&mut self, obs: &mut Obs, reading: &Reading) -> anyhow::Result<()> {
+    let context = template::context(reading);
+
+    for (index, text) in self.config.text.iter().enumerate() {
+      let rendered = text.template.render(&context);
+
+      if self.text[index].as_ref() == Some(&rendered) {
+        continue;
+      }
+
+      let response = obs
+        .request(
+          "SetInputSettings",
+          json!({ "inputName": text.source, "inputSettings": { "text": rendered } }),
+        )
+        .await?;
+
+      // only cached once set, so failed updates are retried
+      match response {
+        Ok(_) => {
+          self.text[index] = Some(rendered);
+          self.text_failed[index] = false;
+        }
+        Err(e) if !self.text_failed[index] => {
+          warn!("obs text source `{}` not updated: {}", text.source, e);
+
+          self.text_failed[index] = true;
+        }
+        Err(_) => {}
+      }
+    }
+
+    for (index, item) in self.config.item.iter().enumerate() {
+      let Some(id) = self.item_ids[index] else {
+        continue;
+      };
+
+      let visible = item.visible.test(&context);
+
+      if self.visible[index] == Some(visible) {
+        continue;
+      }
+
+      let response = obs
+        .request(
+          "SetSceneItemEnabled",
+          json!({ "sceneName": item.scene, "sceneItemId": id, "sceneItemEnabled": visible }),
+        )
+        .await?;
+
+      if let Err(e) = response {
+        warn!("obs scene item `{}` not toggled: {}", item.source, e);
+      }
+
+      self.visible[index] = Some(visible);
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures_util::{SinkExt, StreamExt};
+  use serde_json::Value;
+  use tokio::net::TcpListener;
+  use tokio::sync::mpsc;
+  use tokio_tungstenite::accept_async;
+  use tokio_tungstenite::tungstenite::Message;
+
+  use super::*;
+  use crate::config::ObsText;
+  use crate::template::Template;
+
+  /// Accepts one connection without authentication, rejects the first
+  /// SetInputSettings and forwards every request
+  async fn mock_obs() -> (String, mpsc::UnboundedReceiver<Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("ws://{}", listener.local_addr().unwrap());
+    let (requests, received) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut socket = accept_async(stream).await.unwrap();
+
+      let message = |op: u64, d: Value| Message::Text(json!({ "op": op, "d": d }).to_string());
+
+      socket.send(message(0, json!({ "rpcVersion": 1 }))).await.unwrap();
+
+      let mut rejected = false;
+
+      while let Some(Ok(Message::Text(text))) = socket.next().await {
+        let request: Value = serde_json::from_str(&text).unwrap();
+
+        match request["op"].as_u64() {
+          Some(1) => socket
+            .send(message(2, json!({ "negotiatedRpcVersion": 1 })))
+            .await
+            .unwrap(),
+          Some(6) => {
+            let data = &request["d"];
+            let result = data["requestType"] != "SetInputSettings" || rejected;
+            rejected = true;
+
+            let response = json!({
+              "requestType": data["requestType"],
+              "requestId": data["requestId"],
+              "requestStatus": { "result": result, "code": if result { 100 } else { 600 } },
+            });
+
+            socket.send(message(7, response)).await.unwrap();
+            requests.send(data.clone()).unwrap();
+          }
+          _ => {}
+        }
+      }
+    });
+
+    (address, received)
+  }
+
+  #[tokio::test]
+  async fn text_retried_until_set() {
+    let (address, mut requests) = mock_obs().await;
+
+    let config = ObsConfig {
+      address,
+      text: vec![ObsText {
+        source: "Heart rate".to_string(),
+        template: Template::new("{reading}").unwrap(),
+      }],
+      ..Default::default()
+    };
+
+    let mut obs = Obs::connect(&config.address, None).await.unwrap();
+    let mut scene = Scene::new(&config, &mut obs).await.unwrap();
+
+    for _ in 0..3 {
+      scene.update(&mut obs, &Reading::Value(72)).await.unwrap();
+    }
+
+    obs.close().await;
+
+    let mut sent = Vec::new();
+
+    while let Some(request) = requests.recv().await {
+      sent.push(request);
+    }
+
+    assert_eq!(sent.len(), 2, "{sent:?}");
+
+    for request in sent {
+      assert_eq!(request["requestType"], "SetInputSettings");
+      assert_eq!(request["requestData"]["inputSettings"]["text"], "72");
+    }
+  }
+}
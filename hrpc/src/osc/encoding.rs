@@ -0,0 +1,188 @@
+use crate::config::{FloatCurve, FloatEncoding, FloatRange};
+
+pub fn encode(encoding: &FloatEncoding, reading: f32) -> f32 {
+  if reading == 0.0 {
+    return encoding.none;
+  }
+
+  match &encoding.curve {
+    FloatCurve::Linear(range) => output(range, linear(range, reading)),
+    FloatCurve::Logarithmic(range) => output(range, logarithmic(range, reading)),
+    FloatCurve::Table { table: points } => table(points, reading),
+  }
+}
+
+fn output(range: &FloatRange, progress: f32) -> f32 {
+  let progress = if range.clamp {
+    progress.clamp(0.0, 1.0)
+  } else {
+    progress
+  };
+
+  range.output_min + (range.output_max - range.output_min) * progress
+}
+
+/// a zero width range is a step at `input_min`
+fn step(range: &FloatRange, reading: f32) -> f32 {
+  if reading < range.input_min {
+    0.0
+  } else {
+    1.0
+  }
+}
+
+fn linear(range: &FloatRange, reading: f32) -> f32 {
+  let span = range.input_max - range.input_min;
+
+  if span == 0.0 {
+    return step(range, reading);
+  }
+
+  (reading - range.input_min) / span
+}
+
+/// `ln(1 + x)` of the distance from `input_min`, so an `input_min` of 0 is
+/// fine. readings below `input_min` map to 0
+fn logarithmic(range: &FloatRange, reading: f32) -> f32 {
+  let span = range.input_max - range.input_min;
+
+  if span <= 0.0 {
+    return step(range, reading);
+  }
+
+  let distance = (reading - range.input_min).max(0.0);
+
+  distance.ln_1p() / span.ln_1p()
+}
+
+fn table(points: &[(f32, f32)], reading: f32) -> f32 {
+  let (Some(first), Some(last)) = (points.first(), points.last()) else {
+    return 0.0;
+  };
+
+  if reading <= first.0 {
+    return first.1;
+  }
+
+  for pair in points.windows(2) {
+    let ((from_in, from_out), (to_in, to_out)) = (pair[0], pair[1]);
+
+    if reading > to_in {
+      continue;
+    }
+
+    if to_in == from_in {
+      return to_out;
+    }
+
+    return from_out + (to_out - from_out) * (reading - from_in) / (to_in - from_in);
+  }
+
+  last.1
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn range(input_min: f32, input_max: f32, clamp: bool) -> FloatRange {
+    FloatRange {
+      input_min,
+      input_max,
+      output_min: 0.0,
+      output_max: 1.0,
+      clamp,
+    }
+  }
+
+  fn encoding(curve: FloatCurve) -> FloatEncoding {
+    FloatEncoding { curve, none: -1.0 }
+  }
+
+  fn assert_near(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+  }
+
+  #[test]
+  fn zero_is_none() {
+    for curve in [
+      FloatCurve::Linear(range(0.0, 256.0, false)),
+      FloatCurve::Logarithmic(range(0.0, 256.0, false)),
+      FloatCurve::Table {
+        table: vec![(0.0, 0.5), (255.0, 1.0)],
+      },
+    ] {
+      assert_eq!(encode(&encoding(curve), 0.0), -1.0);
+    }
+  }
+
+  #[test]
+  fn default_linear() {
+    let float = FloatEncoding::linear(0.0, 256.0, -1.0, 1.0, false);
+
+    assert_eq!(encode(&float, 0.0), 0.0);
+    assert_near(encode(&float, 128.0), 0.0);
+    assert_near(encode(&float, 255.0), 255.0 * 0.0078125 - 1.0);
+  }
+
+  #[test]
+  fn linear_clamp() {
+    let clamped = encoding(FloatCurve::Linear(range(50.0, 160.0, true)));
+    let unclamped = encoding(FloatCurve::Linear(range(50.0, 160.0, false)));
+
+    assert_near(encode(&clamped, 105.0), 0.5);
+    assert_near(encode(&clamped, 1.0), 0.0);
+    assert_near(encode(&clamped, 255.0), 1.0);
+    assert_near(encode(&unclamped, 1.0), -49.0 / 110.0);
+    assert_near(encode(&unclamped, 255.0), 205.0 / 110.0);
+  }
+
+  #[test]
+  fn zero_width_range() {
+    for curve in [
+      FloatCurve::Linear(range(100.0, 100.0, true)),
+      FloatCurve::Logarithmic(range(100.0, 100.0, true)),
+    ] {
+      let encoding = encoding(curve);
+
+      assert_eq!(encode(&encoding, 1.0), 0.0);
+      assert_eq!(encode(&encoding, 99.0), 0.0);
+      assert_eq!(encode(&encoding, 100.0), 1.0);
+      assert_eq!(encode(&encoding, 255.0), 1.0);
+    }
+  }
+
+  #[test]
+  fn logarithmic() {
+    let full = encoding(FloatCurve::Logarithmic(range(0.0, 255.0, false)));
+
+    assert_near(encode(&full, 255.0), 1.0);
+    assert_near(encode(&full, 15.0), 16f32.ln() / 256f32.ln());
+    assert!(encode(&full, 60.0) > 60.0 / 255.0);
+
+    let above = encoding(FloatCurve::Logarithmic(range(50.0, 150.0, true)));
+
+    assert_eq!(encode(&above, 1.0), 0.0);
+    assert_eq!(encode(&above, 255.0), 1.0);
+  }
+
+  #[test]
+  fn table_points() {
+    let encoding = encoding(FloatCurve::Table {
+      table: vec![(50.0, 0.0), (100.0, 0.3), (100.0, 0.5), (160.0, 1.0)],
+    });
+
+    assert_eq!(encode(&encoding, 1.0), 0.0);
+    assert_near(encode(&encoding, 75.0), 0.15);
+    assert_near(encode(&encoding, 100.0), 0.3);
+    assert_near(encode(&encoding, 130.0), 0.75);
+    assert_eq!(encode(&encoding, 255.0), 1.0);
+  }
+
+  #[test]
+  fn empty_table() {
+    let encoding = encoding(FloatCurve::Table { table: vec![] });
+
+    assert_eq!(encode(&encoding, 80.0), 0.0);
+  }
+}
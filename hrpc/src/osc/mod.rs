@@ -1,5 +1,6 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 use anyhow::Context;
 use rosc::encoder::encode;
@@ -7,8 +8,13 @@ use rosc::{OscMessage, OscPacket, OscType};
 use tokio::net::UdpSocket;
 use tokio::time::interval;
 
-use crate::config::Config;
-use crate::reading;
+use self::smooth::Smoother;
+use crate::config::{Config, FloatEncoding};
+use crate::metrics::{self, Sink};
+use crate::{reading, session, shutdown, zone};
+
+mod encoding;
+mod smooth;
 
 const INT_PATHS: &[&str] = &[
   "/avatar/parameters/HR",
@@ -19,14 +25,26 @@ const INT_PATHS: &[&str] = &[
 const FLOAT_PATH: &str = "/avatar/parameters/floatHR";
 const PERCENT_PATH: &str = "/avatar/parameters/percentHR";
 const ACTIVE_PATH: &str = "/avatar/parameters/isHRConnected";
+/// index of the heart rate zone, 0 for none
+const ZONE_PATH: &str = "/avatar/parameters/zoneHR";
+/// rolling average over the first of `session.windows`, 0 for none
+const AVERAGE_PATH: &str = "/avatar/parameters/avgHR";
+/// 1 rising, 0 steady or unknown, -1 falling
+const TREND_PATH: &str = "/avatar/parameters/trendHR";
 
 pub fn osc_thread(config: Config) {
   tokio::task::block_in_place(|| {
     let rt = tokio::runtime::Runtime::new().unwrap();
 
     rt.block_on(async move {
-      if let Err(e) = osc_task(config).await {
-        error!("osc_task error: {}", e);
+      tokio::select! {
+        result = osc_task(config) => {
+          if let Err(e) = result {
+            error!("osc_task error: {}", e);
+            metrics::error(Sink::Osc);
+          }
+        }
+        _ = shutdown::wait() => {}
       }
     });
   })
@@ -38,8 +56,29 @@ async fn osc_task(config: Config) -> anyhow::Result<()> {
     return Ok(());
   }
 
+  let interpolation = &config.osc.interpolation;
+
+  let mut float_interval = interval(if interpolation.enable {
+    interpolation.update_interval
+  } else {
+    config.osc.update_interval
+  });
   let mut interval = interval(config.osc.update_interval);
 
+  let float = &config.osc.float;
+  let percent = config.osc.percent.clone().unwrap_or_else(|| {
+    FloatEncoding::linear(
+      config.osc.percent_min as f32,
+      config.osc.percent_max as f32,
+      0.0,
+      1.0,
+      true,
+    )
+  });
+
+  let mut smoother = Smoother::new(interpolation.smoothing);
+  let mut last_float = Instant::now();
+
   let host: IpAddr = config.osc.host.parse().context("failed to parse host")?;
 
   let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
@@ -51,16 +90,35 @@ async fn osc_task(config: Config) -> anyhow::Result<()> {
   info!("osc ready");
 
   loop {
-    interval.tick().await;
+    tokio::select! {
+      _ = interval.tick() => {
+        let reading = reading::get().as_u8();
+
+        send_ints(&socket, addr, reading).await?;
 
-    let reading = reading::get();
+        if !interpolation.enable {
+          send_float(&socket, addr, FLOAT_PATH, encoding::encode(float, reading as f32)).await?;
+          send_float(&socket, addr, PERCENT_PATH, encoding::encode(&percent, reading as f32)).await?;
+        }
 
-    let reading = reading.as_u8();
+        send_active(&socket, addr, reading).await?;
 
-    send_ints(&socket, addr, reading).await?;
-    send_float(&socket, addr, reading).await?;
-    send_percent(&socket, addr, reading, config.osc.percent_min, config.osc.percent_max).await?;
-    send_active(&socket, addr, reading).await?;
+        if !config.zones.zone.is_empty() {
+          send_int(&socket, addr, ZONE_PATH, zone::get().map_or(0, |zone| zone.index as i32)).await?;
+        }
+
+        send_stats(&socket, addr).await?;
+      }
+      _ = float_interval.tick(), if interpolation.enable => {
+        let elapsed = last_float.elapsed();
+        last_float = Instant::now();
+
+        let reading = smoother.update(reading::get().as_u8(), elapsed);
+
+        send_float(&socket, addr, FLOAT_PATH, encoding::encode(float, reading)).await?;
+        send_float(&socket, addr, PERCENT_PATH, encoding::encode(&percent, reading)).await?;
+      }
+    }
   }
 }
 
@@ -68,58 +126,35 @@ async fn send_ints(socket: &UdpSocket, addr: SocketAddr, reading: u8) -> anyhow:
   let values = [reading, reading % 10, reading / 10 % 10, reading / 100 % 10];
 
   for (path, value) in INT_PATHS.iter().zip(values.iter()) {
-    let message = OscPacket::Message(OscMessage {
-      addr: path.to_string(),
-      args: vec![OscType::Int(*value as i32)],
-    });
-
-    let buf = encode(&message)?;
-    socket.send_to(&buf, addr).await?;
+    send(socket, addr, path, OscType::Int(*value as i32)).await?;
   }
 
   Ok(())
 }
 
-async fn send_float(socket: &UdpSocket, addr: SocketAddr, reading: u8) -> anyhow::Result<()> {
-  let float_hr = if reading == 0 {
-    0.0
-  } else {
-    reading as f32 * 0.0078125 - 1.0
-  };
-
-  let message = OscPacket::Message(OscMessage {
-    addr: FLOAT_PATH.to_string(),
-    args: vec![OscType::Float(float_hr)],
-  });
-
-  let buf = encode(&message)?;
-  socket.send_to(&buf, addr).await?;
-
-  Ok(())
+async fn send_float(socket: &UdpSocket, addr: SocketAddr, path: &str, value: f32) -> anyhow::Result<()> {
+  send(socket, addr, path, OscType::Float(value)).await
 }
 
-fn percent(reading: u8, min: u8, max: u8) -> f32 {
-  if reading < min {
-    return 0.0;
-  }
-
-  if reading > max {
-    return 1.0;
-  }
+async fn send_stats(socket: &UdpSocket, addr: SocketAddr) -> anyhow::Result<()> {
+  let session = session::get();
 
-  (reading - min) as f32 / (max - min) as f32
-}
+  let average = session
+    .as_ref()
+    .and_then(|session| session.averages().first()?.1)
+    .map_or(0, |average| average.round() as i32);
 
-async fn send_percent(socket: &UdpSocket, addr: SocketAddr, reading: u8, min: u8, max: u8) -> anyhow::Result<()> {
-  let message = OscPacket::Message(OscMessage {
-    addr: PERCENT_PATH.to_string(),
-    args: vec![OscType::Float(percent(reading, min, max))],
-  });
+  let trend = session
+    .as_ref()
+    .and_then(|session| session.trend())
+    .map_or(0, |trend| trend.sign());
 
-  let buf = encode(&message)?;
-  socket.send_to(&buf, addr).await?;
+  send_int(socket, addr, AVERAGE_PATH, average).await?;
+  send_int(socket, addr, TREND_PATH, trend).await
+}
 
-  Ok(())
+async fn send_int(socket: &UdpSocket, addr: SocketAddr, path: &str, value: i32) -> anyhow::Result<()> {
+  send(socket, addr, path, OscType::Int(value)).await
 }
 
 static ACTIVE: AtomicBool = AtomicBool::new(false);
@@ -135,13 +170,18 @@ async fn send_active(socket: &UdpSocket, addr: SocketAddr, reading: u8) -> anyho
 
   debug!("sending active: {}", active);
 
+  send(socket, addr, ACTIVE_PATH, OscType::Bool(active)).await
+}
+
+/// Sends a message with a single argument and counts it in the metrics
+pub async fn send(socket: &UdpSocket, addr: SocketAddr, path: &str, arg: OscType) -> anyhow::Result<()> {
   let message = OscPacket::Message(OscMessage {
-    addr: ACTIVE_PATH.to_string(),
-    args: vec![OscType::Bool(active)],
+    addr: path.to_string(),
+    args: vec![arg],
   });
 
-  let buf = encode(&message)?;
-  socket.send_to(&buf, addr).await?;
+  socket.send_to(&encode(&message)?, addr).await?;
+  metrics::osc_packet();
 
   Ok(())
 }
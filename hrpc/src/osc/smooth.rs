@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// Exponentially eases towards the latest reading
+pub struct Smoother {
+  value: Option<f32>,
+  smoothing: Duration,
+}
+
+impl Smoother {
+  pub fn new(smoothing: Duration) -> Self {
+    Self { value: None, smoothing }
+  }
+
+  /// Advance by `elapsed` towards `target` and return the eased value.
+  ///
+  /// A reading of `0` (no reading) and the first reading after it are not
+  /// eased, so connecting and disconnecting don't sweep through the range.
+  pub fn update(&mut self, target: u8, elapsed: Duration) -> f32 {
+    if target == 0 {
+      self.value = None;
+      return 0.0;
+    }
+
+    let target = target as f32;
+
+    let value = match self.value {
+      Some(value) if !self.smoothing.is_zero() => {
+        let alpha = 1.0 - (-elapsed.as_secs_f32() / self.smoothing.as_secs_f32()).exp();
+        value + (target - value) * alpha
+      }
+      _ => target,
+    };
+
+    self.value = Some(value);
+
+    value
+  }
+}
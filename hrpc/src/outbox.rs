@@ -0,0 +1,247 @@
+//! Bounded queue with retry backoff for the http sinks
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+
+use crate::config::DeliveryConfig;
+
+/// Items waiting to be delivered, the oldest are dropped once full
+pub struct Outbox<T> {
+  queue: VecDeque<T>,
+  capacity: usize,
+  retry_delay: Duration,
+  retry_delay_max: Duration,
+  /// delay after the next failure
+  delay: Duration,
+  retry_at: Option<Instant>,
+  /// items were dropped since the last delivery
+  dropping: bool,
+}
+
+impl<T> Outbox<T> {
+  pub fn new(config: &DeliveryConfig) -> Self {
+    Self {
+      queue: VecDeque::new(),
+      capacity: config.buffer.max(1),
+      retry_delay: config.retry_delay,
+      retry_delay_max: config.retry_delay_max.max(config.retry_delay),
+      delay: config.retry_delay,
+      retry_at: None,
+      dropping: false,
+    }
+  }
+
+  pub fn push(&mut self, item: T) {
+    if self.queue.len() >= self.capacity {
+      if !self.dropping {
+        warn!("buffer of {} is full, dropping the oldest", self.capacity);
+      }
+
+      self.queue.pop_front();
+      self.dropping = true;
+    }
+
+    self.queue.push_back(item);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.queue.is_empty()
+  }
+
+  pub fn first(&self) -> Option<&T> {
+    self.queue.front()
+  }
+
+  /// Up to `count` items, oldest first
+  pub fn front(&self, count: usize) -> impl Iterator<Item = &T> {
+    self.queue.iter().take(count)
+  }
+
+  /// Whether a delivery should be attempted now
+  pub fn is_due(&self) -> bool {
+    !self.queue.is_empty() && self.retry_at.is_none_or(|at| at <= Instant::now())
+  }
+
+  /// When the next delivery should be attempted, `None` if empty
+  pub fn next_attempt(&self) -> Option<Instant> {
+    if self.queue.is_empty() {
+      return None;
+    }
+
+    Some(self.retry_at.unwrap_or_else(Instant::now))
+  }
+
+  /// Removes the `count` oldest items and resets the backoff
+  pub fn sent(&mut self, count: usize) {
+    self.queue.drain(..count.min(self.queue.len()));
+    self.delay = self.retry_delay;
+    self.retry_at = None;
+    self.dropping = false;
+  }
+
+  /// Backs off before the next attempt, returns the delay
+  pub fn failed(&mut self) -> Duration {
+    let delay = self.delay;
+
+    self.retry_at = Some(Instant::now() + delay);
+    self.delay = (self.delay * 2).min(self.retry_delay_max);
+
+    delay
+  }
+}
+
+/// Waits until `at`, forever if `None`
+pub async fn wait(at: Option<Instant>) {
+  match at {
+    Some(at) => sleep_until(at).await,
+    None => std::future::pending().await,
+  }
+}
+
+/// Local http server the sinks deliver to in tests
+#[cfg(test)]
+pub mod stand_in {
+  use std::net::SocketAddr;
+  use std::sync::{Arc, Mutex};
+
+  use axum::extract::State;
+  use axum::http::StatusCode;
+  use axum::routing::post;
+  use axum::Router;
+  use tokio::net::TcpListener;
+  use tokio::sync::oneshot;
+  use tokio::task::JoinHandle;
+
+  type Received = Arc<Mutex<Vec<String>>>;
+
+  /// Accepts every post and keeps its body until stopped
+  pub struct StandIn {
+    pub url: String,
+    address: SocketAddr,
+    received: Received,
+    server: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+  }
+
+  impl StandIn {
+    pub async fn start() -> Self {
+      let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+      let address = listener.local_addr().unwrap();
+
+      let mut stand_in = Self {
+        url: format!("http://{address}/write"),
+        address,
+        received: Received::default(),
+        server: None,
+      };
+
+      stand_in.serve(listener);
+
+      stand_in
+    }
+
+    /// Refuses connections until restarted
+    pub async fn stop(&mut self) {
+      if let Some((stop, server)) = self.server.take() {
+        let _ = stop.send(());
+        server.await.unwrap();
+      }
+    }
+
+    /// On the same address
+    pub async fn restart(&mut self) {
+      self.serve(TcpListener::bind(self.address).await.unwrap());
+    }
+
+    /// Bodies received since the last call, oldest first
+    pub fn received(&self) -> Vec<String> {
+      std::mem::take(&mut self.received.lock().unwrap())
+    }
+
+    fn serve(&mut self, listener: TcpListener) {
+      let (stop, stopped) = oneshot::channel();
+
+      let app = Router::new()
+        .route("/write", post(receive))
+        .with_state(self.received.clone());
+
+      let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+          .with_graceful_shutdown(async {
+            let _ = stopped.await;
+          })
+          .await
+          .unwrap();
+      });
+
+      self.server = Some((stop, server));
+    }
+  }
+
+  async fn receive(State(received): State<Received>, body: String) -> StatusCode {
+    received.lock().unwrap().push(body);
+
+    StatusCode::NO_CONTENT
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn outbox(buffer: usize) -> Outbox<u32> {
+    Outbox::new(&DeliveryConfig {
+      buffer,
+      retry_delay: Duration::from_secs(1),
+      retry_delay_max: Duration::from_secs(5),
+      timeout: Duration::from_secs(10),
+    })
+  }
+
+  #[test]
+  fn drops_oldest() {
+    let mut outbox = outbox(3);
+
+    for item in 0..5 {
+      outbox.push(item);
+    }
+
+    assert_eq!(outbox.front(10).copied().collect::<Vec<_>>(), [2, 3, 4]);
+
+    outbox.sent(2);
+
+    assert_eq!(outbox.first(), Some(&4));
+  }
+
+  #[test]
+  fn backoff() {
+    let mut outbox = outbox(10);
+    outbox.push(1);
+
+    let delays: Vec<u64> = (0..5).map(|_| outbox.failed().as_secs()).collect();
+
+    assert_eq!(delays, [1, 2, 4, 5, 5]);
+    assert!(!outbox.is_due());
+    assert!(outbox.next_attempt().unwrap() > Instant::now());
+  }
+
+  #[test]
+  fn sent_resets_backoff() {
+    let mut outbox = outbox(10);
+    outbox.push(1);
+    outbox.push(2);
+
+    outbox.failed();
+    outbox.failed();
+    outbox.sent(1);
+
+    assert!(outbox.is_due());
+    assert_eq!(outbox.failed(), Duration::from_secs(1));
+
+    outbox.sent(1);
+
+    assert!(outbox.is_empty());
+    assert_eq!(outbox.next_attempt(), None);
+  }
+}
@@ -0,0 +1,52 @@
+//! Named profiles switched at runtime, `{profile}` in templates
+
+use std::sync::Mutex;
+
+use anyhow::bail;
+
+use crate::event::{self, EventKind};
+
+static PROFILES: Mutex<Profiles> = Mutex::new(Profiles {
+  names: Vec::new(),
+  active: None,
+});
+
+struct Profiles {
+  names: Vec<String>,
+  active: Option<String>,
+}
+
+/// Makes `names` switchable and activates the first
+pub fn init(names: &[String]) {
+  *PROFILES.lock().unwrap() = Profiles {
+    names: names.to_vec(),
+    active: names.first().cloned(),
+  };
+}
+
+/// `None` without any `profiles` configured
+pub fn get() -> Option<String> {
+  PROFILES.lock().unwrap().active.clone()
+}
+
+/// Activates `name` and emits [`EventKind::Profile`] if it changed
+pub fn switch(name: &str) -> anyhow::Result<()> {
+  let mut profiles = PROFILES.lock().unwrap();
+
+  if !profiles.names.iter().any(|known| known == name) {
+    bail!("unknown profile `{name}`, expected one of {:?}", profiles.names);
+  }
+
+  if profiles.active.as_deref() == Some(name) {
+    return Ok(());
+  }
+
+  info!("switching to profile `{name}`");
+
+  profiles.active = Some(name.to_string());
+  drop(profiles);
+
+  event::emit(EventKind::Profile { name: name.to_string() });
+
+  Ok(())
+}
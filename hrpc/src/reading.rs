@@ -41,6 +41,7 @@ pub fn get() -> Reading {
   Reading::Value(reading)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Reading {
   None,
   Frozen(u8),
@@ -59,6 +60,15 @@ impl Reading {
   pub fn is_none(&self) -> bool {
     matches!(self, Reading::None)
   }
+
+  /// `connected`, `frozen` or `disconnected`
+  pub fn state(&self) -> &'static str {
+    match self {
+      Reading::None => "disconnected",
+      Reading::Frozen(_) => "frozen",
+      Reading::Value(_) => "connected",
+    }
+  }
 }
 
 impl Display for Reading {
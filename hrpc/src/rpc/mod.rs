@@ -1,11 +1,30 @@
+use std::fmt::Display;
+use std::mem::{discriminant, Discriminant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use anyhow::anyhow;
-use discord_rich_presence::activity::Activity;
 use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
-use tokio::time::interval;
+use tokio::time::{interval, sleep, timeout};
+
+use self::presence::Presence;
+pub use self::status::{status, Status};
+use crate::config::{Config, DisconnectedPolicy};
+use crate::metrics::{self, Sink};
+use crate::reading::{self, Reading};
+use crate::shutdown;
+
+mod presence;
+mod status;
+
+/// How often the reading is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// For every ipc call, discord is treated as gone after it
+const IPC_TIMEOUT: Duration = Duration::from_secs(5);
 
-use crate::config::Config;
-use crate::reading;
-use crate::template::Template;
+/// Shared with the blocking pool, the ipc socket blocks
+type Client = Arc<Mutex<DiscordIpcClient>>;
 
 pub fn rpc_thread(config: Config) {
   tokio::task::block_in_place(|| {
@@ -14,8 +33,12 @@ pub fn rpc_thread(config: Config) {
     rt.block_on(async move {
       if let Err(e) = rpc_task(config).await {
         error!("rpc_task error: {}", e);
+        metrics::error(Sink::Rpc);
       }
     });
+
+    // rather than waiting for an ipc call stuck on a hung pipe
+    rt.shutdown_background();
   })
 }
 
@@ -25,56 +48,162 @@ async fn rpc_task(config: Config) -> anyhow::Result<()> {
     return Ok(());
   }
 
-  let mut client = DiscordIpcClient::new(&config.rpc.id).map_err(ah)?;
+  let client: Client = Arc::new(Mutex::new(DiscordIpcClient::new(&config.rpc.id).map_err(ah)?));
 
-  client.connect().map_err(ah)?;
+  loop {
+    if !connect(&client, &config).await {
+      return Ok(());
+    }
 
-  info!("rpc ready");
+    info!("rpc ready");
 
-  let mut interval = interval(config.rpc.update_interval);
+    match presence_loop(&client, &config).await {
+      Ok(()) => {
+        debug!("clearing presence");
 
-  loop {
-    interval.tick().await;
+        // a hung pipe would time out twice
+        if ipc(&client, |client| client.clear_activity()).await.is_ok() {
+          let _ = ipc(&client, |client| client.close()).await;
+        }
 
-    match reading::get() {
-      reading::Reading::None => disconnected_activity(&mut client, &config)?,
-      reading::Reading::Frozen(r) => frozen_activity(&mut client, &config, r)?,
-      reading::Reading::Value(r) => activity(&mut client, &config, r)?,
-    };
+        return Ok(());
+      }
+      Err(e) => {
+        warn!("discord connection lost: {e}");
+        metrics::error(Sink::Rpc);
+
+        status::set(Status::Reconnecting);
+
+        let _ = ipc(&client, |client| client.close()).await;
+      }
+    }
   }
 }
 
-fn activity(client: &mut DiscordIpcClient, config: &Config, reading: u8) -> anyhow::Result<()> {
-  let mut state = Template::new(config.rpc.templates.state.clone());
-  state.add("reading", reading.to_string());
+/// Runs `call` on the blocking pool, giving up after [`IPC_TIMEOUT`]
+async fn ipc<F, E>(client: &Client, call: F) -> anyhow::Result<()>
+where
+  F: FnOnce(&mut DiscordIpcClient) -> Result<(), E> + Send + 'static,
+  E: Display,
+{
+  let client = client.clone();
+
+  let result = timeout(
+    IPC_TIMEOUT,
+    tokio::task::spawn_blocking(move || call(&mut client.lock().unwrap()).map_err(|e| e.to_string())),
+  )
+  .await
+  .map_err(|_| anyhow!("no response from discord within {}ms", IPC_TIMEOUT.as_millis()))?;
+
+  result?.map_err(|e| anyhow!(e))
+}
 
-  let details = config.rpc.templates.details.clone();
-  let state = state.render();
+/// Retries with backoff until connected, `false` on shutdown
+async fn connect(client: &Client, config: &Config) -> bool {
+  let mut delay = config.rpc.reconnect_delay;
 
-  let activity = Activity::new().details(&details).state(&state);
+  if status::status() != Status::Reconnecting {
+    status::set(Status::Waiting);
+  }
 
-  client.set_activity(activity).map_err(ah)
-}
+  loop {
+    let result = tokio::select! {
+      result = ipc(client, |client| client.connect()) => result,
+      _ = shutdown::wait() => return false,
+    };
 
-fn frozen_activity(client: &mut DiscordIpcClient, config: &Config, reading: u8) -> anyhow::Result<()> {
-  let mut state = Template::new(config.rpc.templates.frozen_state.clone());
-  state.add("reading", reading.to_string());
+    match result {
+      Ok(()) => {
+        status::set(Status::Connected);
+        return true;
+      }
+      Err(e) => debug!("failed to connect to discord, retrying in {}ms: {e}", delay.as_millis()),
+    }
 
-  let details = config.rpc.templates.frozen_details.clone();
-  let state = state.render();
+    tokio::select! {
+      _ = sleep(delay) => {}
+      _ = shutdown::wait() => return false,
+    }
 
-  client
-    .set_activity(Activity::new().details(&details).state(&state))
-    .map_err(ah)
+    delay = (delay * 2).min(config.rpc.reconnect_delay_max);
+  }
 }
 
-fn disconnected_activity(client: &mut DiscordIpcClient, config: &Config) -> anyhow::Result<()> {
-  let details = config.rpc.templates.disconnected_details.clone();
-  let state = config.rpc.templates.disconnected_state.clone();
+/// Updates presence on changes until shutdown or a failed update.
+///
+/// Value changes are coalesced into at most one update per `update_interval`,
+/// state changes (connected, frozen, disconnected, cleared) are sent as soon as
+/// `min_interval` allows
+async fn presence_loop(client: &Client, config: &Config) -> anyhow::Result<()> {
+  let mut interval = interval(POLL_INTERVAL);
+
+  // `None` presence is cleared
+  let mut last: Option<(Option<Presence>, Discriminant<Reading>, Instant)> = None;
+  let mut disconnected_since: Option<Instant> = None;
+
+  loop {
+    tokio::select! {
+      _ = interval.tick() => {}
+      _ = shutdown::wait() => return Ok(()),
+    }
+
+    let reading = reading::get();
+    let state = discriminant(&reading);
+
+    if reading.is_none() {
+      disconnected_since.get_or_insert_with(Instant::now);
+    } else {
+      disconnected_since = None;
+    }
+
+    let disconnected_for = disconnected_since.map(|since| since.elapsed()).unwrap_or_default();
+
+    let presence = match config.rpc.disconnected {
+      DisconnectedPolicy::Clear if reading.is_none() && disconnected_for >= config.rpc.disconnected_grace => None,
+      DisconnectedPolicy::LastSeen if reading.is_none() => {
+        Some(Presence::last_seen(config).unwrap_or_else(|| Presence::render(config, &reading)))
+      }
+      _ => Some(Presence::render(config, &reading)),
+    };
+
+    if let Some((last_presence, last_state, last_update)) = &last {
+      if *last_presence == presence {
+        continue;
+      }
+
+      let wait = if *last_state == state && last_presence.is_some() == presence.is_some() {
+        config.rpc.update_interval.max(config.rpc.min_interval)
+      } else {
+        config.rpc.min_interval
+      };
 
-  client
-    .set_activity(Activity::new().details(&details).state(&state))
-    .map_err(ah)
+      if last_update.elapsed() < wait {
+        continue;
+      }
+    }
+
+    let update = async {
+      match presence.clone() {
+        Some(presence) => {
+          debug!("updating presence");
+
+          ipc(client, move |client| client.set_activity(presence.activity())).await
+        }
+        None => {
+          debug!("clearing presence, disconnected for {}ms", disconnected_for.as_millis());
+
+          ipc(client, |client| client.clear_activity()).await
+        }
+      }
+    };
+
+    tokio::select! {
+      update = update => update?,
+      _ = shutdown::wait() => return Ok(()),
+    }
+
+    last = Some((presence, state, Instant::now()));
+  }
 }
 
 fn ah(err: Box<dyn std::error::Error>) -> anyhow::Error {
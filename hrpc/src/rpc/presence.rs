@@ -0,0 +1,115 @@
+use discord_rich_presence::activity::{Activity, Assets, Button, Timestamps};
+
+use crate::config::Config;
+use crate::reading::Reading;
+use crate::session;
+use crate::template::{self, Context, Template};
+
+/// Rendered activity, compared to skip updates that change nothing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Presence {
+  details: String,
+  state: String,
+  large_image: Option<String>,
+  large_text: Option<String>,
+  small_image: Option<String>,
+  small_text: Option<String>,
+  start: Option<i64>,
+  buttons: Vec<(String, String)>,
+}
+
+impl Presence {
+  pub fn render(config: &Config, reading: &Reading) -> Self {
+    let context = template::context(reading);
+    let templates = &config.rpc.templates;
+
+    let start = if config.rpc.elapsed && !reading.is_none() {
+      session::get()
+        .filter(|session| session.is_active())
+        .map(|session| session.start.timestamp())
+    } else {
+      None
+    };
+
+    Self {
+      details: templates.details.render(&context),
+      state: templates.state.render(&context),
+      large_image: render(&templates.large_image, &context),
+      large_text: render(&templates.large_text, &context),
+      small_image: render(&templates.small_image, &context),
+      small_text: render(&templates.small_text, &context),
+      start,
+      buttons: config
+        .rpc
+        .buttons
+        .iter()
+        .map(|button| (button.label.render(&context), button.url.clone()))
+        .collect(),
+    }
+  }
+
+  /// Last reading as frozen, started when it was received
+  pub fn last_seen(config: &Config) -> Option<Self> {
+    let (value, seen) = session::get()?.last?;
+
+    let mut presence = Self::render(config, &Reading::Frozen(value));
+    presence.start = Some(seen.timestamp());
+
+    Some(presence)
+  }
+
+  pub fn activity(&self) -> Activity<'_> {
+    let mut activity = Activity::new();
+
+    if !self.details.is_empty() {
+      activity = activity.details(&self.details);
+    }
+
+    if !self.state.is_empty() {
+      activity = activity.state(&self.state);
+    }
+
+    if self.large_image.is_some() || self.small_image.is_some() {
+      let mut assets = Assets::new();
+
+      if let Some(large_image) = &self.large_image {
+        assets = assets.large_image(large_image);
+      }
+
+      if let Some(large_text) = &self.large_text {
+        assets = assets.large_text(large_text);
+      }
+
+      if let Some(small_image) = &self.small_image {
+        assets = assets.small_image(small_image);
+      }
+
+      if let Some(small_text) = &self.small_text {
+        assets = assets.small_text(small_text);
+      }
+
+      activity = activity.assets(assets);
+    }
+
+    if let Some(start) = self.start {
+      activity = activity.timestamps(Timestamps::new().start(start));
+    }
+
+    if !self.buttons.is_empty() {
+      let buttons = self
+        .buttons
+        .iter()
+        .map(|(label, url)| Button::new(label, url))
+        .collect();
+
+      activity = activity.buttons(buttons);
+    }
+
+    activity
+  }
+}
+
+/// `None` if unset or rendered empty
+fn render(template: &Option<Template>, context: &Context) -> Option<String> {
+  Some(template.as_ref()?.render(context)).filter(|rendered| !rendered.is_empty())
+}
@@ -0,0 +1,44 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static STATUS: AtomicU8 = AtomicU8::new(Status::Disabled as u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+  Disabled,
+  /// discord isn't running or hasn't been reached yet
+  Waiting,
+  Connected,
+  /// connection lost, retrying
+  Reconnecting,
+}
+
+/// Discord connection status
+pub fn status() -> Status {
+  match STATUS.load(Ordering::Relaxed) {
+    1 => Status::Waiting,
+    2 => Status::Connected,
+    3 => Status::Reconnecting,
+    _ => Status::Disabled,
+  }
+}
+
+pub(super) fn set(status: Status) {
+  let previous = STATUS.swap(status as u8, Ordering::Relaxed);
+
+  if previous != status as u8 {
+    info!("discord {status}");
+  }
+}
+
+impl Display for Status {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Status::Disabled => write!(f, "disabled"),
+      Status::Waiting => write!(f, "waiting for discord"),
+      Status::Connected => write!(f, "connected"),
+      Status::Reconnecting => write!(f, "reconnecting"),
+    }
+  }
+}
@@ -0,0 +1,92 @@
+use std::convert::Infallible;
+
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::Json;
+use chrono::Local;
+use futures_lite::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::reading::{self, Reading};
+use crate::{event, profile, rfc3339, session, shutdown};
+
+/// `/api/current`, times are RFC 3339
+#[derive(Serialize, Debug)]
+pub struct Current {
+  pub bpm: Option<u8>,
+  pub state: &'static str,
+  pub timestamp: String,
+  pub sensor: Option<String>,
+  pub session_start: Option<String>,
+  pub last_seen: Option<String>,
+  pub min: Option<u8>,
+  pub max: Option<u8>,
+  pub avg: Option<f64>,
+  pub profile: Option<String>,
+}
+
+impl Current {
+  pub fn now() -> Self {
+    let reading = reading::get();
+    let session = session::get();
+
+    Self {
+      bpm: match reading {
+        Reading::None => None,
+        Reading::Frozen(value) | Reading::Value(value) => Some(value),
+      },
+      state: reading.state(),
+      timestamp: rfc3339(&Local::now()),
+      sensor: session.as_ref().map(|session| session.sensor.clone()),
+      session_start: session.as_ref().map(|session| rfc3339(&session.start)),
+      last_seen: session
+        .as_ref()
+        .and_then(|session| session.last)
+        .map(|(_, time)| rfc3339(&time)),
+      min: session.as_ref().and_then(|session| session.min),
+      max: session.as_ref().and_then(|session| session.max),
+      avg: session.as_ref().and_then(|session| session.average()),
+      profile: profile::get(),
+    }
+  }
+}
+
+pub async fn current() -> Json<Current> {
+  Json(Current::now())
+}
+
+/// [`Current`] on connecting and after every event, named after the event.
+/// Ends on shutdown so the server can stop
+pub async fn stream() -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+  let initial = stream::once(message("current"));
+
+  let events = stream::unfold(event::subscribe(), |mut receiver| async move {
+    loop {
+      let event = tokio::select! {
+        event = receiver.recv() => event,
+        _ = shutdown::wait() => return None,
+      };
+
+      match event {
+        Ok(event) => return Some((message(event.kind.name()), receiver)),
+        Err(RecvError::Lagged(_)) => continue,
+        Err(RecvError::Closed) => return None,
+      }
+    }
+  });
+
+  Sse::new(initial.chain(events)).keep_alive(KeepAlive::default())
+}
+
+fn message(name: &'static str) -> Result<sse::Event, Infallible> {
+  let event = sse::Event::default().event(name);
+
+  Ok(match event.json_data(Current::now()) {
+    Ok(event) => event,
+    Err(e) => {
+      error!("failed to serialize current reading: {}", e);
+
+      sse::Event::default().event(name)
+    }
+  })
+}
@@ -0,0 +1,88 @@
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+use crate::config::{Config, ServerConfig};
+use crate::metrics::{self, Sink};
+use crate::shutdown;
+
+mod api;
+mod ws;
+
+pub use self::api::Current;
+
+const OVERLAY: &str = include_str!("overlay.html");
+
+pub fn server_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = server_task(config).await {
+        error!("server_task error: {}", e);
+        metrics::error(Sink::Server);
+      }
+    });
+  })
+}
+
+async fn server_task(config: Config) -> anyhow::Result<()> {
+  debug!("server_task start");
+  if !config.server.enable {
+    return Ok(());
+  }
+
+  let listener = TcpListener::bind(config.server.address).await?;
+
+  info!("serving on http://{}", config.server.address);
+
+  serve(listener, config.server).await
+}
+
+/// Serves on `listener` until shutdown
+pub async fn serve(listener: TcpListener, config: ServerConfig) -> anyhow::Result<()> {
+  let mut app = Router::new()
+    .route("/", get(overlay))
+    .route("/overlay", get(overlay))
+    .route("/api/current", get(api::current))
+    .route("/api/stream", get(api::stream))
+    .route("/api/ws", get(ws::ws));
+
+  if config.metrics {
+    app = app.route("/metrics", get(prometheus));
+  }
+
+  let app = app.with_state(config);
+
+  axum::serve(listener, app)
+    .with_graceful_shutdown(shutdown::wait())
+    .await?;
+
+  Ok(())
+}
+
+async fn prometheus() -> ([(header::HeaderName, &'static str); 1], String) {
+  ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render())
+}
+
+async fn overlay(State(config): State<ServerConfig>) -> Result<Html<String>, (StatusCode, String)> {
+  let Some(path) = &config.overlay else {
+    return Ok(Html(OVERLAY.to_string()));
+  };
+
+  match tokio::fs::read_to_string(path).await {
+    Ok(html) => Ok(Html(html)),
+    Err(e) => {
+      error!("failed to read overlay `{}`: {}", path, e);
+
+      Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("failed to read `{path}`: {e}"),
+      ))
+    }
+  }
+}
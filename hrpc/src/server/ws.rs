@@ -0,0 +1,286 @@
+//! `/api/ws`, JSON text messages in both directions
+//!
+//! Requests carry a `type` and an optional `id` that is echoed in the reply:
+//!
+//! - `{"type": "subscribe", "events": ["reading", "state"]}` events to push,
+//!   any of `reading`, `state`, `connected`, `disconnected`, `mark`, `zone` and
+//!   `profile`
+//! - `{"type": "unsubscribe", "events": ["reading"]}`
+//! - `{"type": "current"}` same as `/api/current`
+//! - `{"type": "stats"}` statistics of the current or last session
+//! - `{"type": "reconnect"}` drops the sensor connection and scans again
+//! - `{"type": "mark", "label": "..."}` records a mark event, labels are up to
+//!   100 characters
+//! - `{"type": "profile", "name": "..."}` switches to one of the configured
+//!   `profiles`
+//!
+//! Pushed events look like `{"type": "event", "event": "reading", "time":
+//! "...", ...}`, `state` is sent when the reading changes between connected,
+//! frozen and disconnected
+//!
+//! Browsers let any page open websockets to localhost, so upgrades with an
+//! `Origin` other than this server are refused
+
+use std::collections::BTreeSet;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::api::Current;
+use crate::event::{self, Event, EventKind};
+use crate::reading::{self, Reading};
+use crate::{monitor, profile, rfc3339, session, shutdown};
+
+const EVENTS: &[&str] = &[
+  "reading",
+  "state",
+  "connected",
+  "disconnected",
+  "mark",
+  "zone",
+  "profile",
+];
+
+/// Longest mark label, in characters
+const MAX_LABEL: usize = 100;
+
+#[derive(Deserialize, Debug)]
+struct Request {
+  id: Option<Value>,
+  #[serde(flatten)]
+  command: Command,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+  Subscribe { events: Vec<String> },
+  Unsubscribe { events: Vec<String> },
+  Current,
+  Stats,
+  Reconnect,
+  Mark { label: Option<String> },
+  Profile { name: String },
+}
+
+pub async fn ws(headers: HeaderMap, upgrade: WebSocketUpgrade) -> Response {
+  if !same_origin(&headers) {
+    debug!("refused websocket client from {:?}", headers.get(header::ORIGIN));
+
+    return (StatusCode::FORBIDDEN, "cross-origin websocket clients are not allowed").into_response();
+  }
+
+  upgrade.on_upgrade(|socket| async move {
+    if let Err(e) = client(socket).await {
+      debug!("websocket client error: {}", e);
+    }
+  })
+}
+
+/// Clients without an `Origin` aren't browsers, pages served from here have
+/// the same host
+fn same_origin(headers: &HeaderMap) -> bool {
+  let Some(origin) = headers.get(header::ORIGIN) else {
+    return true;
+  };
+
+  let origin = origin.to_str().ok().and_then(|origin| origin.split_once("://"));
+  let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+
+  match (origin, host) {
+    (Some((_, origin)), Some(host)) => origin.eq_ignore_ascii_case(host),
+    _ => false,
+  }
+}
+
+async fn client(mut socket: WebSocket) -> anyhow::Result<()> {
+  debug!("websocket client connected");
+
+  let mut events = event::subscribe();
+  let mut subscribed = BTreeSet::new();
+  let mut state = reading::get().state();
+
+  send(&mut socket, current(None)).await?;
+
+  loop {
+    tokio::select! {
+      message = socket.recv() => match message {
+        Some(Ok(Message::Text(text))) => {
+          let reply = handle(&text, &mut subscribed);
+
+          send(&mut socket, reply).await?;
+        }
+        Some(Ok(Message::Close(_))) | None => break,
+        Some(Ok(_)) => {}
+        Some(Err(e)) => return Err(e.into()),
+      },
+      event = events.recv() => match event {
+        Ok(event) => {
+          for message in push(&event, &mut state, &subscribed) {
+            send(&mut socket, message).await?;
+          }
+        }
+        Err(RecvError::Lagged(missed)) => send(&mut socket, json!({ "type": "lagged", "missed": missed })).await?,
+        Err(RecvError::Closed) => break,
+      },
+      _ = shutdown::wait() => {
+        let _ = socket.send(Message::Close(None)).await;
+
+        break;
+      }
+    }
+  }
+
+  debug!("websocket client disconnected");
+
+  Ok(())
+}
+
+async fn send(socket: &mut WebSocket, message: Value) -> anyhow::Result<()> {
+  socket.send(Message::Text(message.to_string())).await?;
+
+  Ok(())
+}
+
+fn handle(text: &str, subscribed: &mut BTreeSet<&'static str>) -> Value {
+  let request: Request = match serde_json::from_str(text) {
+    Ok(request) => request,
+    Err(e) => return json!({ "type": "error", "message": e.to_string() }),
+  };
+
+  let id = request.id;
+
+  match request.command {
+    Command::Subscribe { events } | Command::Unsubscribe { events }
+      if events.iter().any(|e| !EVENTS.contains(&e.as_str())) =>
+    {
+      json!({ "type": "error", "id": id, "message": format!("unknown event, expected one of {EVENTS:?}") })
+    }
+    Command::Subscribe { events } => {
+      subscribed.extend(EVENTS.iter().filter(|known| events.iter().any(|e| e == *known)));
+
+      json!({ "type": "subscribed", "id": id, "events": subscribed })
+    }
+    Command::Unsubscribe { events } => {
+      subscribed.retain(|known| !events.iter().any(|e| e == known));
+
+      json!({ "type": "subscribed", "id": id, "events": subscribed })
+    }
+    Command::Current => current(id),
+    Command::Stats => stats(id),
+    Command::Reconnect => {
+      monitor::reconnect();
+
+      json!({ "type": "ok", "id": id })
+    }
+    Command::Mark { label } if label.as_ref().is_some_and(|label| label.chars().count() > MAX_LABEL) => {
+      json!({ "type": "error", "id": id, "message": format!("labels are at most {MAX_LABEL} characters") })
+    }
+    Command::Mark { label } => {
+      event::emit(EventKind::Mark { label });
+
+      json!({ "type": "ok", "id": id })
+    }
+    Command::Profile { name } => match profile::switch(&name) {
+      Ok(()) => json!({ "type": "ok", "id": id }),
+      Err(e) => json!({ "type": "error", "id": id, "message": e.to_string() }),
+    },
+  }
+}
+
+fn current(id: Option<Value>) -> Value {
+  let mut message = json!({ "type": "current", "id": id });
+
+  if let (Some(message), Ok(Value::Object(current))) = (message.as_object_mut(), serde_json::to_value(Current::now())) {
+    message.extend(current);
+  }
+
+  message
+}
+
+fn stats(id: Option<Value>) -> Value {
+  let Some(session) = session::get() else {
+    return json!({ "type": "stats", "id": id, "session": null });
+  };
+
+  json!({
+    "type": "stats",
+    "id": id,
+    "session": {
+      "sensor": session.sensor,
+      "start": rfc3339(&session.start),
+      "end": session.end.as_ref().map(rfc3339),
+      "active": session.is_active(),
+      "duration_secs": session.duration().as_secs(),
+      "samples": session.samples(),
+      "min": session.min,
+      "max": session.max,
+      "avg": session.average(),
+      "avg_window": session.window_average(),
+      "averages": session
+        .averages()
+        .into_iter()
+        .map(|(window, average)| json!({ "window_secs": window.as_secs(), "avg": average }))
+        .collect::<Vec<_>>(),
+      "trend": session.trend(),
+      "trend_rate": session.trend_rate(),
+      "zones": session.zone_times(),
+    },
+  })
+}
+
+/// Messages for subscribed events, tracking `state` to report transitions
+fn push(event: &Event, state: &mut &'static str, subscribed: &BTreeSet<&'static str>) -> Vec<Value> {
+  let time = rfc3339(&event.time);
+
+  let mut messages = Vec::new();
+
+  let mut message = |name: &'static str, fields: Value| {
+    if !subscribed.contains(name) {
+      return;
+    }
+
+    let mut message = json!({ "type": "event", "event": name, "time": time });
+
+    if let (Some(message), Value::Object(fields)) = (message.as_object_mut(), fields) {
+      message.extend(fields);
+    }
+
+    messages.push(message);
+  };
+
+  match &event.kind {
+    EventKind::Connected { sensor } => message("connected", json!({ "sensor": sensor })),
+    EventKind::Disconnected => message("disconnected", json!({})),
+    EventKind::Mark { label } => message("mark", json!({ "label": label })),
+    EventKind::Profile { name } => message("profile", json!({ "name": name })),
+    EventKind::Zone { from, to } => message(
+      "zone",
+      json!({
+        "from": from.as_ref().map(|zone| &zone.name),
+        "to": to.as_ref().map(|zone| &zone.name),
+        "index": to.as_ref().map(|zone| zone.index),
+      }),
+    ),
+    EventKind::Reading(reading) => {
+      let bpm = match reading {
+        Reading::None => None,
+        Reading::Frozen(value) | Reading::Value(value) => Some(*value),
+      };
+
+      message("reading", json!({ "bpm": bpm, "state": reading.state() }));
+
+      if reading.state() != *state {
+        message("state", json!({ "from": *state, "to": reading.state() }));
+
+        *state = reading.state();
+      }
+    }
+  }
+
+  messages
+}
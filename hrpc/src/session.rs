@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+use crate::config::SessionConfig;
+use crate::stats::{self, SessionSummary, Trend, ZoneTime};
+use crate::zone::{self, Zone};
+
+/// Longest gap between readings counted towards time in a zone
+const MAX_ZONE_GAP: Duration = Duration::from_secs(5);
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// A sensor connection, from connecting until disconnecting.
+/// Kept after disconnecting until the next connection
+#[derive(Clone, Debug)]
+pub struct Session {
+  pub sensor: String,
+  pub start: DateTime<Local>,
+  pub end: Option<DateTime<Local>>,
+  pub min: Option<u8>,
+  pub max: Option<u8>,
+  /// last reading and when it was received
+  pub last: Option<(u8, DateTime<Local>)>,
+  sum: u64,
+  count: u64,
+  config: SessionConfig,
+  /// readings within the longest configured window, oldest first
+  history: VecDeque<(Instant, u8)>,
+  /// by zone index
+  zones: BTreeMap<usize, ZoneTime>,
+}
+
+impl Session {
+  pub fn new(sensor: String, config: &SessionConfig) -> Self {
+    Self {
+      sensor,
+      start: Local::now(),
+      end: None,
+      min: None,
+      max: None,
+      last: None,
+      sum: 0,
+      count: 0,
+      config: config.clone(),
+      history: VecDeque::new(),
+      zones: BTreeMap::new(),
+    }
+  }
+
+  /// Adds a reading received at `now` in `zone`, ignored once ended and for
+  /// `0` (no contact)
+  pub fn record(&mut self, value: u8, now: Instant, zone: Option<Zone>) {
+    if value == 0 || !self.is_active() {
+      return;
+    }
+
+    self.min = Some(self.min.map_or(value, |min| min.min(value)));
+    self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    self.last = Some((value, Local::now()));
+    self.sum += value as u64;
+    self.count += 1;
+
+    // the time since the previous reading counts towards the zone it was in,
+    // the zone is only updated for this reading after recording it
+    if let (Some((previous, _)), Some(zone)) = (self.history.back(), zone) {
+      let elapsed = now.duration_since(*previous).min(MAX_ZONE_GAP);
+
+      self
+        .zones
+        .entry(zone.index)
+        .or_insert_with(|| ZoneTime {
+          index: zone.index,
+          name: zone.name,
+          time: Duration::ZERO,
+        })
+        .time += elapsed;
+    }
+
+    self.history.push_back((now, value));
+
+    let retention = self.retention();
+
+    while let Some((time, _)) = self.history.front() {
+      if now.duration_since(*time) <= retention {
+        break;
+      }
+
+      self.history.pop_front();
+    }
+  }
+
+  pub fn average(&self) -> Option<f64> {
+    (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+  }
+
+  /// Average over the last `session.average_window`
+  pub fn window_average(&self) -> Option<f64> {
+    self.average_over(self.config.average_window)
+  }
+
+  /// Average of the readings within `window` before the last one
+  pub fn average_over(&self, window: Duration) -> Option<f64> {
+    let mut count = 0;
+
+    let sum = self
+      .within(window)
+      .map(|(_, value)| *value as u64)
+      .inspect(|_| count += 1)
+      .sum::<u64>();
+
+    (count > 0).then(|| sum as f64 / count as f64)
+  }
+
+  /// [`Session::average_over`] each of `session.windows`
+  pub fn averages(&self) -> Vec<(Duration, Option<f64>)> {
+    self
+      .config
+      .windows
+      .iter()
+      .map(|window| (*window, self.average_over(*window)))
+      .collect()
+  }
+
+  /// Readings within the average window, oldest first
+  pub fn recent(&self) -> impl Iterator<Item = u8> + '_ {
+    self.within(self.config.average_window).map(|(_, value)| *value)
+  }
+
+  /// Change over `session.trend_window` in bpm per minute
+  pub fn trend_rate(&self) -> Option<f64> {
+    stats::slope(self.within(self.config.trend_window))
+  }
+
+  pub fn trend(&self) -> Option<Trend> {
+    self
+      .trend_rate()
+      .map(|rate| Trend::from_rate(rate, self.config.trend_threshold))
+  }
+
+  /// Time spent in each zone, by zone index
+  pub fn zone_times(&self) -> Vec<ZoneTime> {
+    self.zones.values().cloned().collect()
+  }
+
+  /// Readings recorded
+  pub fn samples(&self) -> u64 {
+    self.count
+  }
+
+  pub fn duration(&self) -> Duration {
+    let end = self.end.unwrap_or_else(Local::now);
+
+    (end - self.start).to_std().unwrap_or_default()
+  }
+
+  pub fn is_active(&self) -> bool {
+    self.end.is_none()
+  }
+
+  pub fn summary(&self) -> SessionSummary {
+    SessionSummary {
+      sensor: self.sensor.clone(),
+      start: self.start,
+      end: self.end.unwrap_or_else(Local::now),
+      duration: self.duration(),
+      samples: self.count,
+      min: self.min,
+      avg: self.average(),
+      max: self.max,
+      zones: self.zone_times(),
+    }
+  }
+
+  /// Readings within `window` before the last one
+  fn within(&self, window: Duration) -> impl Iterator<Item = &(Instant, u8)> + Clone {
+    let newest = self.history.back().map(|(time, _)| *time);
+
+    let skip = self
+      .history
+      .iter()
+      .take_while(|(time, _)| newest.is_some_and(|newest| newest.duration_since(*time) > window))
+      .count();
+
+    self.history.range(skip..)
+  }
+
+  /// Longest time readings are needed for
+  fn retention(&self) -> Duration {
+    self
+      .config
+      .windows
+      .iter()
+      .copied()
+      .chain([self.config.average_window, self.config.trend_window])
+      .max()
+      .unwrap_or_default()
+  }
+}
+
+pub fn start(sensor: String, config: &SessionConfig) {
+  *SESSION.lock().unwrap() = Some(Session::new(sensor, config));
+}
+
+/// Adds a reading to the active session, `0` (no contact) is ignored
+pub fn record(value: u8) {
+  if let Some(session) = SESSION.lock().unwrap().as_mut() {
+    session.record(value, Instant::now(), zone::get());
+  }
+}
+
+/// Ends the active session and returns it, `None` if none was active
+pub fn end() -> Option<Session> {
+  let mut session = SESSION.lock().unwrap();
+
+  let session = session.as_mut().filter(|session| session.is_active())?;
+
+  session.end = Some(Local::now());
+
+  Some(session.clone())
+}
+
+/// Current or last session
+pub fn get() -> Option<Session> {
+  SESSION.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn zone(index: usize) -> Option<Zone> {
+    Some(Zone {
+      index,
+      name: format!("zone {index}"),
+    })
+  }
+
+  #[test]
+  fn windows_evict() {
+    let mut session = Session::new("H10".to_string(), &SessionConfig::default());
+    let start = Instant::now();
+
+    // 60 for 200s, then 90 for 200s, every 10s
+    for secs in (0..=400).step_by(10) {
+      let value = if secs <= 200 { 60 } else { 90 };
+
+      session.record(value, start + Duration::from_secs(secs), None);
+    }
+
+    // only the longest window, 300s, is kept
+    assert_eq!(session.history.len(), 31);
+    assert_eq!(session.samples(), 41);
+
+    assert_eq!(session.window_average(), Some(90.0));
+    // 11 readings of 60 from 100s on, 20 of 90
+    assert_eq!(session.average_over(Duration::from_secs(300)), Some(2460.0 / 31.0));
+    assert_eq!(session.recent().count(), 7);
+  }
+
+  #[test]
+  fn trend_follows_readings() {
+    let mut session = Session::new("H10".to_string(), &SessionConfig::default());
+    let start = Instant::now();
+
+    for (secs, value) in [(0, 80), (5, 80), (10, 80)] {
+      session.record(value, start + Duration::from_secs(secs), None);
+    }
+
+    assert_eq!(session.trend(), Some(Trend::Steady));
+
+    // the earlier readings drop out of the 30s window
+    for (secs, value) in [(40, 90), (45, 100), (50, 110)] {
+      session.record(value, start + Duration::from_secs(secs), None);
+    }
+
+    assert_eq!(session.trend(), Some(Trend::Rising));
+
+    for (secs, value) in [(85, 100), (90, 90), (95, 80)] {
+      session.record(value, start + Duration::from_secs(secs), None);
+    }
+
+    assert_eq!(session.trend(), Some(Trend::Falling));
+  }
+
+  #[test]
+  fn zone_time() {
+    let mut session = Session::new("H10".to_string(), &SessionConfig::default());
+    let start = Instant::now();
+
+    // each reading passes the zone before it, the gap counts at most 5s
+    for (secs, zone) in [
+      (0, None),
+      (2, zone(1)),
+      (5, zone(1)),
+      (6, zone(2)),
+      (60, zone(2)),
+      (62, zone(1)),
+    ] {
+      session.record(100, start + Duration::from_secs(secs), zone);
+    }
+
+    let times = session
+      .zone_times()
+      .into_iter()
+      .map(|zone| (zone.index, zone.time.as_secs()))
+      .collect::<Vec<_>>();
+
+    assert_eq!(times, [(1, 7), (2, 6)]);
+
+    // nothing more once ended
+    session.end = Some(Local::now());
+    session.record(100, start + Duration::from_secs(64), zone(1));
+
+    assert_eq!(session.samples(), 6);
+  }
+}
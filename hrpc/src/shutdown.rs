@@ -0,0 +1,22 @@
+use std::sync::LazyLock;
+
+use tokio::sync::watch;
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+/// Ask every task to stop, works across runtimes
+pub fn trigger() {
+  SHUTDOWN.send_replace(true);
+}
+
+pub fn is_triggered() -> bool {
+  *SHUTDOWN.borrow()
+}
+
+/// Resolves once [`trigger`] is called
+pub async fn wait() {
+  let mut receiver = SHUTDOWN.subscribe();
+
+  // the sender is static so this can't fail
+  let _ = receiver.wait_for(|shutdown| *shutdown).await;
+}
@@ -0,0 +1,186 @@
+//! Session statistics beyond the running min, max and average
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Serialize, Serializer};
+
+/// Readings needed to fit a trend
+const TREND_SAMPLES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trend {
+  Rising,
+  Steady,
+  Falling,
+}
+
+impl Trend {
+  /// Steady within `threshold` bpm per minute
+  pub fn from_rate(rate: f64, threshold: f64) -> Self {
+    if rate > threshold {
+      Trend::Rising
+    } else if rate < -threshold {
+      Trend::Falling
+    } else {
+      Trend::Steady
+    }
+  }
+
+  /// `rising`, `steady` or `falling`
+  pub fn name(&self) -> &'static str {
+    match self {
+      Trend::Rising => "rising",
+      Trend::Steady => "steady",
+      Trend::Falling => "falling",
+    }
+  }
+
+  /// `1`, `0` or `-1`
+  pub fn sign(&self) -> i32 {
+    match self {
+      Trend::Rising => 1,
+      Trend::Steady => 0,
+      Trend::Falling => -1,
+    }
+  }
+}
+
+/// Least squares slope of the readings in bpm per minute
+pub fn slope<'a>(samples: impl Iterator<Item = &'a (Instant, u8)> + Clone) -> Option<f64> {
+  let first = samples.clone().next()?.0;
+
+  let points = samples.map(|(time, value)| (time.duration_since(first).as_secs_f64() / 60.0, *value as f64));
+
+  let (mut n, mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+  for (x, y) in points {
+    n += 1.0;
+    sum_x += x;
+    sum_y += y;
+    sum_xx += x * x;
+    sum_xy += x * y;
+  }
+
+  let denominator = n * sum_xx - sum_x * sum_x;
+
+  if n < TREND_SAMPLES as f64 || denominator.abs() < f64::EPSILON {
+    return None;
+  }
+
+  Some((n * sum_xy - sum_x * sum_y) / denominator)
+}
+
+/// Time spent in one heart rate zone
+#[derive(Clone, Debug, Serialize)]
+pub struct ZoneTime {
+  pub index: usize,
+  pub name: String,
+  #[serde(rename = "secs", serialize_with = "secs")]
+  pub time: Duration,
+}
+
+/// Written to the log when a session ends
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionSummary {
+  pub sensor: String,
+  #[serde(serialize_with = "crate::serialize_rfc3339")]
+  pub start: DateTime<Local>,
+  #[serde(serialize_with = "crate::serialize_rfc3339")]
+  pub end: DateTime<Local>,
+  #[serde(rename = "duration_secs", serialize_with = "secs")]
+  pub duration: Duration,
+  pub samples: u64,
+  pub min: Option<u8>,
+  pub avg: Option<f64>,
+  pub max: Option<u8>,
+  pub zones: Vec<ZoneTime>,
+}
+
+impl Display for SessionSummary {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let value = |value: Option<u8>| value.map_or("-".to_string(), |value| value.to_string());
+
+    write!(
+      f,
+      "{} session of {}, {} readings, min {} avg {} max {}",
+      self.sensor,
+      clock(self.duration),
+      self.samples,
+      value(self.min),
+      self.avg.map_or("-".to_string(), |avg| format!("{avg:.1}")),
+      value(self.max)
+    )?;
+
+    if !self.zones.is_empty() {
+      write!(f, ", {}", zone_times(&self.zones))?;
+    }
+
+    Ok(())
+  }
+}
+
+/// `warm up 0:02:10, cardio 0:14:02`
+pub fn zone_times(zones: &[ZoneTime]) -> String {
+  zones
+    .iter()
+    .map(|zone| format!("{} {}", zone.name, clock(zone.time)))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// `1:02:03`
+pub fn clock(duration: Duration) -> String {
+  let secs = duration.as_secs();
+
+  format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.serialize_u64(duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// One reading per second
+  fn samples(values: &[u8]) -> Vec<(Instant, u8)> {
+    let start = Instant::now();
+
+    values
+      .iter()
+      .enumerate()
+      .map(|(index, value)| (start + Duration::from_secs(index as u64), *value))
+      .collect()
+  }
+
+  #[test]
+  fn slope_sign() {
+    let rising = slope(samples(&[60, 61, 62, 63]).iter()).unwrap();
+    let falling = slope(samples(&[90, 88, 86]).iter()).unwrap();
+
+    // bpm per minute
+    assert!((rising - 60.0).abs() < 1e-9, "{rising}");
+    assert!((falling + 120.0).abs() < 1e-9, "{falling}");
+    assert_eq!(slope(samples(&[70, 70, 70]).iter()), Some(0.0));
+
+    assert_eq!(slope(samples(&[60, 61]).iter()), None);
+
+    let start = Instant::now();
+    assert_eq!(slope([(start, 60), (start, 70), (start, 80)].iter()), None);
+  }
+
+  #[test]
+  fn trend_threshold() {
+    assert_eq!(Trend::from_rate(3.5, 3.0), Trend::Rising);
+    assert_eq!(Trend::from_rate(3.0, 3.0), Trend::Steady);
+    assert_eq!(Trend::from_rate(-3.0, 3.0), Trend::Steady);
+    assert_eq!(Trend::from_rate(-3.5, 3.0), Trend::Falling);
+
+    assert_eq!(Trend::Rising.sign(), 1);
+    assert_eq!(Trend::Falling.sign(), -1);
+  }
+}
@@ -0,0 +1,368 @@
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+/// Window averaged for [`Store::daily_resting`]
+const RESTING_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Longest time between readings within a window of [`lowest_average`]
+const MAX_GAP: Duration = Duration::from_secs(10);
+
+const SCHEMA: &str = "
+  CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    sensor TEXT NOT NULL,
+    started INTEGER NOT NULL,
+    ended INTEGER
+  );
+
+  CREATE TABLE IF NOT EXISTS samples (
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    time INTEGER NOT NULL,
+    bpm INTEGER NOT NULL
+  );
+
+  CREATE INDEX IF NOT EXISTS samples_session_time ON samples(session, time);
+  CREATE INDEX IF NOT EXISTS samples_time ON samples(time);
+
+  CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    session INTEGER REFERENCES sessions(id),
+    time INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    detail TEXT
+  );
+
+  CREATE INDEX IF NOT EXISTS events_time ON events(time);
+";
+
+/// Sessions with their reading statistics
+const SESSION_SELECT: &str = "
+  SELECT sessions.id, sensor, started, ended, COUNT(bpm), MIN(bpm), AVG(bpm), MAX(bpm)
+  FROM sessions LEFT JOIN samples ON samples.session = sessions.id";
+
+/// Session history, times are stored as unix milliseconds
+pub struct Store {
+  connection: Connection,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoredSession {
+  pub id: i64,
+  pub sensor: String,
+  pub start: DateTime<Local>,
+  pub end: Option<DateTime<Local>>,
+  pub samples: u64,
+  pub min: Option<u8>,
+  pub avg: Option<f64>,
+  pub max: Option<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoredEvent {
+  pub session: Option<i64>,
+  pub time: DateTime<Local>,
+  pub kind: String,
+  pub detail: Option<String>,
+}
+
+impl Store {
+  /// Creates the database and tables if missing. Sessions left open by a
+  /// crash are closed at their last sample
+  pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let connection = Connection::open(path)?;
+
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.execute_batch(SCHEMA)?;
+
+    connection.execute(
+      "UPDATE sessions
+       SET ended = COALESCE((SELECT MAX(time) FROM samples WHERE session = sessions.id), started)
+       WHERE ended IS NULL",
+      [],
+    )?;
+
+    Ok(Self { connection })
+  }
+
+  pub fn start_session(&self, sensor: &str, start: DateTime<Local>) -> anyhow::Result<i64> {
+    self.connection.execute(
+      "INSERT INTO sessions (sensor, started) VALUES (?1, ?2)",
+      params![sensor, start.timestamp_millis()],
+    )?;
+
+    Ok(self.connection.last_insert_rowid())
+  }
+
+  pub fn end_session(&self, id: i64, end: DateTime<Local>) -> anyhow::Result<()> {
+    self.connection.execute(
+      "UPDATE sessions SET ended = ?2 WHERE id = ?1",
+      params![id, end.timestamp_millis()],
+    )?;
+
+    Ok(())
+  }
+
+  /// In one transaction
+  pub fn insert_samples(&mut self, session: i64, samples: &[(DateTime<Local>, u8)]) -> anyhow::Result<()> {
+    let transaction = self.connection.transaction()?;
+
+    {
+      let mut statement = transaction.prepare_cached("INSERT INTO samples (session, time, bpm) VALUES (?1, ?2, ?3)")?;
+
+      for (time, bpm) in samples {
+        statement.execute(params![session, time.timestamp_millis(), bpm])?;
+      }
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+  }
+
+  pub fn insert_event(
+    &self,
+    session: Option<i64>,
+    time: DateTime<Local>,
+    kind: &str,
+    detail: Option<&str>,
+  ) -> anyhow::Result<()> {
+    self.connection.execute(
+      "INSERT INTO events (session, time, kind, detail) VALUES (?1, ?2, ?3, ?4)",
+      params![session, time.timestamp_millis(), kind, detail],
+    )?;
+
+    Ok(())
+  }
+
+  /// Sessions that started within `from..to`, oldest first
+  pub fn sessions(&self, from: DateTime<Local>, to: DateTime<Local>) -> anyhow::Result<Vec<StoredSession>> {
+    let mut statement = self.connection.prepare_cached(&format!(
+      "{SESSION_SELECT} WHERE started >= ?1 AND started < ?2 GROUP BY sessions.id ORDER BY started"
+    ))?;
+
+    let rows = statement.query_map(params![from.timestamp_millis(), to.timestamp_millis()], stored_session)?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  pub fn session(&self, id: i64) -> anyhow::Result<Option<StoredSession>> {
+    let mut statement = self
+      .connection
+      .prepare_cached(&format!("{SESSION_SELECT} WHERE sessions.id = ?1 GROUP BY sessions.id"))?;
+
+    Ok(statement.query_row([id], stored_session).optional()?)
+  }
+
+  /// Readings of a session, oldest first
+  pub fn samples(&self, session: i64) -> anyhow::Result<Vec<(DateTime<Local>, u8)>> {
+    let mut statement = self
+      .connection
+      .prepare_cached("SELECT time, bpm FROM samples WHERE session = ?1 ORDER BY time")?;
+
+    let rows = statement.query_map([session], |row| Ok((local(row.get(0)?), row.get(1)?)))?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  /// Readings of every session within `from..to`, oldest first
+  pub fn samples_between(
+    &self,
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+  ) -> anyhow::Result<Vec<(DateTime<Local>, u8)>> {
+    let mut statement = self
+      .connection
+      .prepare_cached("SELECT time, bpm FROM samples WHERE time >= ?1 AND time < ?2 ORDER BY time")?;
+
+    let rows = statement.query_map(params![from.timestamp_millis(), to.timestamp_millis()], |row| {
+      Ok((local(row.get(0)?), row.get(1)?))
+    })?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  pub fn events(&self, from: DateTime<Local>, to: DateTime<Local>) -> anyhow::Result<Vec<StoredEvent>> {
+    let mut statement = self
+      .connection
+      .prepare_cached("SELECT session, time, kind, detail FROM events WHERE time >= ?1 AND time < ?2 ORDER BY time")?;
+
+    let rows = statement.query_map(params![from.timestamp_millis(), to.timestamp_millis()], |row| {
+      Ok(StoredEvent {
+        session: row.get(0)?,
+        time: local(row.get(1)?),
+        kind: row.get(2)?,
+        detail: row.get(3)?,
+      })
+    })?;
+
+    Ok(rows.collect::<Result<_, _>>()?)
+  }
+
+  /// Resting heart rate per local day within `from..=to`, the lowest average
+  /// over any 5 minutes. Days without a full window of readings are skipped
+  pub fn daily_resting(&self, from: NaiveDate, to: NaiveDate) -> anyhow::Result<Vec<(NaiveDate, f64)>> {
+    let mut days = Vec::new();
+
+    for day in from.iter_days().take_while(|day| *day <= to) {
+      let (Some(start), Some(end)) = (start_of(day), day.checked_add_days(Days::new(1)).and_then(start_of)) else {
+        continue;
+      };
+
+      if let Some(resting) = lowest_average(&self.samples_between(start, end)?, RESTING_WINDOW) {
+        days.push((day, resting));
+      }
+    }
+
+    Ok(days)
+  }
+}
+
+fn stored_session(row: &Row) -> rusqlite::Result<StoredSession> {
+  Ok(StoredSession {
+    id: row.get(0)?,
+    sensor: row.get(1)?,
+    start: local(row.get(2)?),
+    end: row.get::<_, Option<i64>>(3)?.map(local),
+    samples: row.get(4)?,
+    min: row.get(5)?,
+    avg: row.get(6)?,
+    max: row.get(7)?,
+  })
+}
+
+fn local(millis: i64) -> DateTime<Local> {
+  Local.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+fn start_of(day: NaiveDate) -> Option<DateTime<Local>> {
+  Local.from_local_datetime(&day.and_hms_opt(0, 0, 0)?).earliest()
+}
+
+/// Lowest average of the readings within any `window`, `None` if the readings
+/// don't span a whole window. Windows with a gap above [`MAX_GAP`] between
+/// readings don't count
+fn lowest_average(samples: &[(DateTime<Local>, u8)], window: Duration) -> Option<f64> {
+  let window = chrono::Duration::from_std(window).ok()?;
+  let max_gap = chrono::Duration::from_std(MAX_GAP).ok()?;
+
+  let mut lowest: Option<f64> = None;
+  let mut start = 0;
+  let mut sum = 0u64;
+  // first reading after the latest gap
+  let mut resumed = 0;
+
+  for (end, (time, bpm)) in samples.iter().enumerate() {
+    if end > 0 && *time - samples[end - 1].0 > max_gap {
+      resumed = end;
+    }
+
+    sum += *bpm as u64;
+
+    while *time - samples[start].0 > window {
+      sum -= samples[start].1 as u64;
+      start += 1;
+    }
+
+    // without a gap inside, and either a reading just before the window was
+    // dropped or the remaining ones span all of it
+    if start >= resumed && (start > resumed || *time - samples[start].0 >= window) {
+      let average = sum as f64 / (end - start + 1) as f64;
+
+      lowest = Some(lowest.map_or(average, |lowest| lowest.min(average)));
+    }
+  }
+
+  lowest
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(day: u32, hour: u32, secs: i64) -> DateTime<Local> {
+    let start = NaiveDate::from_ymd_opt(2024, 1, day)
+      .unwrap()
+      .and_hms_opt(hour, 0, 0)
+      .unwrap();
+
+    Local.from_local_datetime(&start).earliest().unwrap() + chrono::Duration::seconds(secs)
+  }
+
+  /// One reading per second over `secs`
+  fn readings(day: u32, hour: u32, secs: std::ops::Range<i64>, bpm: u8) -> Vec<(DateTime<Local>, u8)> {
+    secs.map(|secs| (at(day, hour, secs), bpm)).collect()
+  }
+
+  #[test]
+  fn lowest_window() {
+    let samples = [readings(10, 8, 0..600, 70), readings(10, 8, 600..1000, 60)].concat();
+
+    assert_eq!(lowest_average(&samples, RESTING_WINDOW), Some(60.0));
+    assert_eq!(lowest_average(&readings(10, 8, 0..240, 50), RESTING_WINDOW), None);
+  }
+
+  #[test]
+  fn gaps_break_windows() {
+    // a single reading after a dropout isn't a 5 minute average
+    let mut samples = readings(10, 8, 0..600, 70);
+    samples.push((at(10, 9, 0), 40));
+
+    assert_eq!(lowest_average(&samples, RESTING_WINDOW), Some(70.0));
+
+    let samples = [readings(10, 8, 0..200, 50), readings(10, 8, 215..400, 50)].concat();
+
+    assert_eq!(lowest_average(&samples, RESTING_WINDOW), None);
+  }
+
+  fn store() -> Store {
+    let mut store = Store::open(":memory:").unwrap();
+
+    let first = store.start_session("H10", at(10, 8, 0)).unwrap();
+    store
+      .insert_samples(
+        first,
+        &[readings(10, 8, 0..360, 65), readings(10, 8, 360..720, 55)].concat(),
+      )
+      .unwrap();
+    store.end_session(first, at(10, 8, 720)).unwrap();
+
+    let second = store.start_session("H10", at(11, 20, 0)).unwrap();
+    store.insert_samples(second, &readings(11, 20, 0..120, 80)).unwrap();
+
+    store
+  }
+
+  #[test]
+  fn sessions_in_range() {
+    let store = store();
+
+    let sessions = store.sessions(at(10, 0, 0), at(11, 0, 0)).unwrap();
+
+    assert_eq!(sessions.len(), 1);
+
+    let session = &sessions[0];
+
+    assert_eq!(session.sensor, "H10");
+    assert_eq!((session.start, session.end), (at(10, 8, 0), Some(at(10, 8, 720))));
+    assert_eq!((session.samples, session.min, session.max), (720, Some(55), Some(65)));
+    assert_eq!(session.avg.map(|avg| avg.round()), Some(60.0));
+
+    let sessions = store.sessions(at(10, 0, 0), at(12, 0, 0)).unwrap();
+
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[1].end, None);
+  }
+
+  #[test]
+  fn resting_per_day() {
+    let store = store();
+
+    let day = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+
+    // the second day has only 2 minutes of readings
+    assert_eq!(store.daily_resting(day(9), day(12)).unwrap(), [(day(10), 55.0)]);
+  }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Local};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, Instant};
+
+pub use self::db::{Store, StoredEvent, StoredSession};
+use crate::config::Config;
+use crate::event::{self, Event, EventKind};
+use crate::metrics::{self, Sink};
+use crate::reading::Reading;
+use crate::{session, shutdown};
+
+mod db;
+
+pub fn store_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(async move {
+      if let Err(e) = store_task(config).await {
+        error!("store_task error: {}", e);
+        metrics::error(Sink::Store);
+      }
+    });
+  })
+}
+
+/// Database calls block, which is fine as nothing else runs on this runtime
+async fn store_task(config: Config) -> anyhow::Result<()> {
+  debug!("store_task start");
+  if !config.store.enable {
+    return Ok(());
+  }
+
+  let mut events = event::subscribe();
+
+  let mut store = Store::open(&config.store.path)?;
+  let mut recorder = Recorder::default();
+
+  let interval = config.store.flush_interval;
+  let mut flush = interval_at(Instant::now() + interval, interval);
+
+  loop {
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(event) => recorder.handle(&mut store, event)?,
+        Err(RecvError::Lagged(missed)) => warn!("store_task fell behind and missed {missed} events"),
+        Err(RecvError::Closed) => break,
+      },
+      _ = flush.tick() => recorder.flush(&mut store)?,
+      _ = shutdown::wait() => break,
+    }
+  }
+
+  debug!("store_task flushing on shutdown");
+
+  recorder.flush(&mut store)?;
+  recorder.end(&store, Local::now())
+}
+
+/// The stored session and readings not written yet
+#[derive(Default)]
+struct Recorder {
+  session: Option<i64>,
+  pending: Vec<(DateTime<Local>, u8)>,
+  frozen: bool,
+}
+
+impl Recorder {
+  fn handle(&mut self, store: &mut Store, Event { time, kind }: Event) -> anyhow::Result<()> {
+    match kind {
+      EventKind::Connected { sensor } => {
+        self.flush(store)?;
+        self.end(store, time)?;
+
+        let id = store.start_session(&sensor, time)?;
+
+        store.insert_event(Some(id), time, "connected", Some(&sensor))?;
+
+        self.session = Some(id);
+      }
+      EventKind::Disconnected => {
+        self.flush(store)?;
+
+        store.insert_event(self.session, time, "disconnected", None)?;
+
+        self.end(store, time)?;
+      }
+      EventKind::Reading(Reading::Value(bpm)) => {
+        if self.frozen {
+          self.frozen = false;
+
+          store.insert_event(self.session, time, "unfrozen", None)?;
+        }
+
+        self.pending.push((time, bpm));
+      }
+      EventKind::Reading(Reading::Frozen(bpm)) if !self.frozen => {
+        self.frozen = true;
+
+        store.insert_event(self.session, time, "frozen", Some(&bpm.to_string()))?;
+      }
+      EventKind::Mark { label } => {
+        store.insert_event(self.session, time, "mark", label.as_deref())?;
+      }
+      EventKind::Zone { to, .. } => {
+        store.insert_event(self.session, time, "zone", to.as_ref().map(|zone| zone.name.as_str()))?;
+      }
+      EventKind::Profile { name } => {
+        store.insert_event(self.session, time, "profile", Some(name.as_str()))?;
+      }
+      _ => {}
+    }
+
+    Ok(())
+  }
+
+  fn flush(&mut self, store: &mut Store) -> anyhow::Result<()> {
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+
+    // connected before subscribing
+    if self.session.is_none() {
+      if let Some(session) = session::get().filter(|session| session.is_active()) {
+        self.session = Some(store.start_session(&session.sensor, session.start)?);
+      }
+    }
+
+    let Some(id) = self.session else {
+      self.pending.clear();
+
+      return Ok(());
+    };
+
+    store.insert_samples(id, &self.pending)?;
+    self.pending.clear();
+
+    Ok(())
+  }
+
+  fn end(&mut self, store: &Store, time: DateTime<Local>) -> anyhow::Result<()> {
+    self.frozen = false;
+
+    match self.session.take() {
+      Some(id) => store.end_session(id, time),
+      None => Ok(()),
+    }
+  }
+}
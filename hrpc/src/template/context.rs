@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+
+use super::Value;
+use crate::reading::Reading;
+use crate::{profile, session, stats, zone};
+
+/// Variables available to every template
+pub const VARIABLES: &[&str] = &[
+  "reading",
+  "frozen",
+  "disconnected",
+  "state",
+  "min",
+  "max",
+  "last_reading",
+  "last_seen",
+  "avg",
+  "avg_window",
+  "sensor",
+  "duration",
+  "duration_secs",
+  "session_start",
+  "session",
+  "now",
+  "date",
+  "timestamp",
+  "zone",
+  "zone_index",
+  "zone_secs",
+  "zone_times",
+  "trend",
+  "trend_rate",
+  "profile",
+];
+
+#[derive(Default, Debug)]
+pub struct Context {
+  variables: HashMap<&'static str, Value>,
+  /// recent readings for helpers, oldest first
+  pub(super) history: Vec<u8>,
+  /// `session.windows` in seconds and their averages, for `avg(seconds)`
+  pub(super) averages: Vec<(u64, Option<f64>)>,
+}
+
+impl Context {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn add(&mut self, key: &'static str, value: impl Into<Value>) {
+    self.variables.insert(key, value.into());
+  }
+
+  pub fn get(&self, key: &str) -> Value {
+    self.variables.get(key).cloned().unwrap_or(Value::None)
+  }
+}
+
+/// [`VARIABLES`] for `reading` and the current session.
+/// Session variables are kept after disconnecting until the next connection
+pub fn context(reading: &Reading) -> Context {
+  context_at(reading, Local::now())
+}
+
+/// [`context`] with `now` and the time variables derived from it set to `now`
+pub fn context_at(reading: &Reading, now: DateTime<Local>) -> Context {
+  let mut context = Context::new();
+
+  context.add(
+    "reading",
+    match reading {
+      Reading::None => None,
+      Reading::Frozen(reading) | Reading::Value(reading) => Some(*reading),
+    },
+  );
+  context.add("frozen", matches!(reading, Reading::Frozen(_)));
+  context.add("disconnected", reading.is_none());
+  context.add("state", reading.state());
+  context.add("now", now.timestamp());
+  // `1985-04-12T23:20:50`
+  context.add("timestamp", now.format("%Y-%m-%dT%H:%M:%S").to_string());
+  context.add("date", now.format("%Y-%m-%d").to_string());
+  context.add("profile", profile::get());
+
+  let zone = zone::get();
+
+  context.add("zone", zone.as_ref().map(|zone| zone.name.clone()));
+  let zone_index = zone.as_ref().map(|zone| zone.index);
+
+  context.add("zone_index", zone_index.map(|index| index as u64));
+
+  if let Some(session) = session::get() {
+    let duration = session.duration().as_secs();
+
+    context.add("min", session.min);
+    context.add("max", session.max);
+    context.add("last_reading", session.last.map(|(value, _)| value));
+    context.add("last_seen", session.last.map(|(_, time)| time.timestamp()));
+    context.add("avg", session.average());
+    context.add("avg_window", session.window_average());
+    context.add("sensor", session.sensor.clone());
+    context.add("duration", stats::clock(session.duration()));
+    context.add("duration_secs", duration);
+    context.add("session_start", session.start.timestamp());
+    // `20240131-180405`, usable in file names
+    context.add("session", session.start.format("%Y%m%d-%H%M%S").to_string());
+
+    let zone_times = session.zone_times();
+
+    context.add(
+      "zone_secs",
+      zone_index
+        .and_then(|index| zone_times.iter().find(|time| time.index == index))
+        .map(|time| time.time.as_secs()),
+    );
+    context.add("zone_times", stats::zone_times(&zone_times));
+    context.add("trend", session.trend().map(|trend| trend.name()));
+    // one decimal
+    context.add(
+      "trend_rate",
+      session.trend_rate().map(|rate| (rate * 10.0).round() / 10.0),
+    );
+
+    context.history = session.recent().collect();
+    context.averages = session
+      .averages()
+      .into_iter()
+      .map(|(window, average)| (window.as_secs(), average))
+      .collect();
+  }
+
+  context
+}
@@ -0,0 +1,165 @@
+//! Template functions for text overlays
+//!
+//! - `heart()`, `heart(on, off)` glyph alternating with the beat, `❤` and `♡`
+//!   by default
+//! - `bar(value, min, max)`, `bar(value, min, max, width, full, empty)` gauge
+//!   like `████░░`, 1 to 256 wide
+//! - `sparkline()`, `sparkline(count)` recent readings like `▁▃▅▇`, 20 by
+//!   default
+//! - `trend()` arrow for `{trend}`, one of `↑ → ↓`, `→` until there is one
+//! - `avg(seconds)` rolling average over one of `session.windows`, others are
+//!   rejected on load
+
+use std::ops::RangeInclusive;
+
+use chrono::Local;
+
+use super::{Context, Value};
+
+const SPARKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Widest `bar`, wider ones are clamped
+const MAX_BAR_WIDTH: usize = 256;
+
+/// Accepted argument counts, `None` for unknown functions
+pub fn arity(name: &str) -> Option<RangeInclusive<usize>> {
+  match name {
+    "heart" => Some(0..=2),
+    "bar" => Some(3..=6),
+    "sparkline" => Some(0..=1),
+    "trend" => Some(0..=0),
+    "avg" => Some(1..=1),
+    _ => None,
+  }
+}
+
+pub fn call(name: &str, args: &[Value], context: &Context) -> Value {
+  let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::None);
+
+  let text = |i: usize, default: &str| match arg(i) {
+    Value::None => default.to_string(),
+    value => value.to_string(),
+  };
+
+  let number = |i: usize, default: f64| arg(i).as_f64().unwrap_or(default);
+
+  let rendered = match name {
+    "heart" => heart(&context.get("reading"), text(0, "❤"), text(1, "♡")),
+    "bar" => bar(
+      &arg(0),
+      number(1, 0.0),
+      number(2, 255.0),
+      (number(3, 10.0) as usize).clamp(1, MAX_BAR_WIDTH),
+      &text(4, "█"),
+      &text(5, "░"),
+    ),
+    "sparkline" => sparkline(&context.history, number(0, 20.0) as usize),
+    "trend" => trend(&context.get("trend")).to_string(),
+    "avg" => {
+      let secs = number(0, -1.0);
+
+      return context
+        .averages
+        .iter()
+        .find(|(window, _)| *window as f64 == secs)
+        .and_then(|(_, average)| *average)
+        .into();
+    }
+    _ => return Value::None,
+  };
+
+  Value::Str(rendered)
+}
+
+/// `on` for the first half of every beat at the current reading
+fn heart(reading: &Value, on: String, off: String) -> String {
+  let Some(bpm) = reading.as_f64().filter(|bpm| *bpm > 0.0) else {
+    return off;
+  };
+
+  let seconds = Local::now().timestamp_millis() as f64 / 1000.0;
+
+  if (seconds * bpm / 60.0).fract() < 0.5 {
+    on
+  } else {
+    off
+  }
+}
+
+fn bar(value: &Value, min: f64, max: f64, width: usize, full: &str, empty: &str) -> String {
+  let progress = match value.as_f64() {
+    Some(value) if max > min => ((value - min) / (max - min)).clamp(0.0, 1.0),
+    Some(value) if value >= min => 1.0,
+    _ => 0.0,
+  };
+
+  let filled = (progress * width as f64).round() as usize;
+
+  format!("{}{}", full.repeat(filled), empty.repeat(width - filled))
+}
+
+/// Scaled between the lowest and highest shown reading
+fn sparkline(history: &[u8], count: usize) -> String {
+  let shown = &history[history.len().saturating_sub(count)..];
+
+  let (Some(min), Some(max)) = (shown.iter().min(), shown.iter().max()) else {
+    return String::new();
+  };
+
+  shown
+    .iter()
+    .map(|value| {
+      if max == min {
+        return SPARKS[SPARKS.len() / 2];
+      }
+
+      let index = (value - min) as usize * (SPARKS.len() - 1) / (max - min) as usize;
+
+      SPARKS[index]
+    })
+    .collect()
+}
+
+/// The session trend, see [`Trend::name`](crate::stats::Trend::name)
+fn trend(trend: &Value) -> char {
+  match trend.to_string().as_str() {
+    "rising" => '↑',
+    "falling" => '↓',
+    _ => '→',
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn bar(args: &[Value]) -> String {
+    call("bar", args, &Context::new()).to_string()
+  }
+
+  #[test]
+  fn bar_width() {
+    let args = |width: f64| [Value::Int(120), Value::Int(40), Value::Int(200), Value::Float(width)];
+
+    assert_eq!(bar(&args(10.0)), "█████░░░░░");
+    assert_eq!(bar(&args(0.0)).chars().count(), 1);
+    assert_eq!(bar(&args(-5.0)).chars().count(), 1);
+    assert_eq!(bar(&args(1e12)).chars().count(), MAX_BAR_WIDTH);
+    assert_eq!(bar(&args(f64::NAN)).chars().count(), 1);
+  }
+
+  #[test]
+  fn trend_arrows() {
+    let arrow = |trend: Option<&str>| {
+      let mut context = Context::new();
+      context.add("trend", trend);
+
+      call("trend", &[], &context).to_string()
+    };
+
+    assert_eq!(arrow(Some("rising")), "↑");
+    assert_eq!(arrow(Some("steady")), "→");
+    assert_eq!(arrow(Some("falling")), "↓");
+    assert_eq!(arrow(None), "→");
+  }
+}
@@ -0,0 +1,458 @@
+//! Templates for text outputs
+//!
+//! - `{reading}` inserts a variable, `{{` and `}}` are literal braces
+//! - `{reading * 2}`, `{reading + 10 > 100}` arithmetic and comparisons
+//! - `{reading|default:"--"}` filters: `default`, `upper`, `lower`, `round`,
+//!   `floor`, `ceil`, `abs`, `json`, `date:"%H:%M"` for unix timestamps
+//! - `{reading:>3}`, `{reading:0>3}`, `{reading / 3:.1}` format specs like
+//!   `std::fmt`
+//! - `{if frozen}~{elif disconnected}-{else}{end}` conditionals with `and`,
+//!   `or`, `not`
+//! - `{bar(reading, 40, 200)}`, `{sparkline()}` helper functions: `heart`,
+//!   `bar`, `sparkline`, `trend`
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Deserializer};
+
+pub use self::context::{context, context_at, Context, VARIABLES};
+use self::parse::{Align, Expr, Filter, Node, Op, Spec};
+pub use self::value::Value;
+
+mod context;
+mod helpers;
+mod parse;
+mod value;
+
+#[derive(Clone, Debug)]
+pub struct Template {
+  source: String,
+  nodes: Vec<Node>,
+}
+
+impl Template {
+  pub fn new(source: &str) -> anyhow::Result<Self> {
+    Ok(Self {
+      source: source.to_string(),
+      nodes: parse::parse(source)?,
+    })
+  }
+
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  /// Every variable referenced anywhere in the template
+  pub fn variables(&self) -> BTreeSet<&str> {
+    let mut variables = BTreeSet::new();
+
+    visit_nodes(&self.nodes, &mut |expr| {
+      if let Expr::Variable(name) = expr {
+        variables.insert(name.as_str());
+      }
+    });
+
+    variables
+  }
+
+  /// Errors on variables not in `known` and `avg` calls over other than
+  /// `windows`
+  pub fn validate(&self, known: &[&str], windows: &[Duration]) -> anyhow::Result<()> {
+    let unknown = self
+      .variables()
+      .into_iter()
+      .filter(|variable| !known.contains(variable))
+      .collect::<Vec<_>>();
+
+    if !unknown.is_empty() {
+      bail!(
+        "unknown variable(s) {} in template `{}`, available: {}",
+        unknown.join(", "),
+        self.source,
+        known.join(", ")
+      );
+    }
+
+    let mut averages = Vec::new();
+
+    visit_nodes(&self.nodes, &mut |expr| {
+      if let Expr::Call(name, args) = expr {
+        if name == "avg" {
+          averages.extend(args.first());
+        }
+      }
+    });
+
+    for arg in averages {
+      let Expr::Literal(value @ (Value::Int(_) | Value::Float(_))) = arg else {
+        bail!("`avg` takes a number of seconds in template `{}`", self.source);
+      };
+
+      let secs = value.as_f64().unwrap_or_default();
+
+      if !windows.iter().any(|window| window.as_secs() as f64 == secs) {
+        bail!(
+          "`avg({secs})` in template `{}` isn't one of `session.windows`: {:?}",
+          self.source,
+          windows.iter().map(Duration::as_secs).collect::<Vec<_>>()
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn render(&self, context: &Context) -> String {
+    let mut rendered = String::new();
+    render_nodes(&self.nodes, context, &mut rendered);
+    rendered
+  }
+
+  /// For templates used as conditions, true unless rendered empty, `false`
+  /// or `0`
+  pub fn test(&self, context: &Context) -> bool {
+    !matches!(self.render(context).trim(), "" | "false" | "0")
+  }
+}
+
+impl<'de> Deserialize<'de> for Template {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where D: Deserializer<'de> {
+    let source = String::deserialize(deserializer)?;
+
+    Template::new(&source).map_err(serde::de::Error::custom)
+  }
+}
+
+/// Calls `visit` with every expression, including nested ones
+fn visit_nodes<'a>(nodes: &'a [Node], visit: &mut impl FnMut(&'a Expr)) {
+  for node in nodes {
+    match node {
+      Node::Text(_) => {}
+      Node::Output { expr, .. } => visit_expr(expr, visit),
+      Node::If { branches, otherwise } => {
+        for (condition, body) in branches {
+          visit_expr(condition, visit);
+          visit_nodes(body, visit);
+        }
+
+        visit_nodes(otherwise, visit);
+      }
+    }
+  }
+}
+
+fn visit_expr<'a>(expr: &'a Expr, visit: &mut impl FnMut(&'a Expr)) {
+  visit(expr);
+
+  match expr {
+    Expr::Literal(_) | Expr::Variable(_) => {}
+    Expr::Not(expr) | Expr::Negate(expr) => visit_expr(expr, visit),
+    Expr::Binary(left, _, right) => {
+      visit_expr(left, visit);
+      visit_expr(right, visit);
+    }
+    Expr::Call(_, args) => {
+      for arg in args {
+        visit_expr(arg, visit);
+      }
+    }
+  }
+}
+
+fn render_nodes(nodes: &[Node], context: &Context, rendered: &mut String) {
+  for node in nodes {
+    match node {
+      Node::Text(text) => rendered.push_str(text),
+      Node::Output { expr, filters, spec } => {
+        let value = filters
+          .iter()
+          .fold(eval(expr, context), |value, filter| apply(filter, value));
+
+        match spec {
+          Some(spec) => rendered.push_str(&format_spec(spec, &value)),
+          None => rendered.push_str(&value.to_string()),
+        }
+      }
+      Node::If { branches, otherwise } => {
+        let body = branches
+          .iter()
+          .find(|(condition, _)| eval(condition, context).is_truthy())
+          .map(|(_, body)| body)
+          .unwrap_or(otherwise);
+
+        render_nodes(body, context, rendered);
+      }
+    }
+  }
+}
+
+fn eval(expr: &Expr, context: &Context) -> Value {
+  match expr {
+    Expr::Literal(value) => value.clone(),
+    Expr::Variable(name) => context.get(name),
+    Expr::Not(expr) => Value::Bool(!eval(expr, context).is_truthy()),
+    Expr::Negate(expr) => match eval(expr, context) {
+      Value::Int(value) => value.checked_neg().into(),
+      Value::Float(value) => Value::Float(-value),
+      _ => Value::None,
+    },
+    Expr::Binary(left, Op::And, right) => {
+      let left = eval(left, context);
+
+      if left.is_truthy() {
+        eval(right, context)
+      } else {
+        left
+      }
+    }
+    Expr::Binary(left, Op::Or, right) => {
+      let left = eval(left, context);
+
+      if left.is_truthy() {
+        left
+      } else {
+        eval(right, context)
+      }
+    }
+    Expr::Binary(left, op, right) => binary(*op, eval(left, context), eval(right, context)),
+    Expr::Call(name, args) => {
+      let args = args.iter().map(|arg| eval(arg, context)).collect::<Vec<_>>();
+
+      helpers::call(name, &args, context)
+    }
+  }
+}
+
+fn binary(op: Op, left: Value, right: Value) -> Value {
+  use std::cmp::Ordering::{Equal, Greater, Less};
+
+  let ordering = || left.compare(&right);
+
+  match op {
+    Op::Eq => return Value::Bool(ordering() == Some(Equal)),
+    Op::Ne => return Value::Bool(ordering() != Some(Equal)),
+    Op::Lt => return Value::Bool(ordering() == Some(Less)),
+    Op::Le => return Value::Bool(matches!(ordering(), Some(Less | Equal))),
+    Op::Gt => return Value::Bool(ordering() == Some(Greater)),
+    Op::Ge => return Value::Bool(matches!(ordering(), Some(Greater | Equal))),
+    _ => {}
+  }
+
+  match (op, &left, &right) {
+    (Op::Add, Value::Str(_), _) | (Op::Add, _, Value::Str(_)) => Value::Str(format!("{left}{right}")),
+    (Op::Add, Value::Int(a), Value::Int(b)) => a.checked_add(*b).into(),
+    (Op::Sub, Value::Int(a), Value::Int(b)) => a.checked_sub(*b).into(),
+    (Op::Mul, Value::Int(a), Value::Int(b)) => a.checked_mul(*b).into(),
+    (Op::Rem, Value::Int(a), Value::Int(b)) => a.checked_rem(*b).into(),
+    _ => {
+      let (Some(a), Some(b)) = (left.as_f64(), right.as_f64()) else {
+        return Value::None;
+      };
+
+      match op {
+        Op::Add => Value::Float(a + b),
+        Op::Sub => Value::Float(a - b),
+        Op::Mul => Value::Float(a * b),
+        Op::Div | Op::Rem if b == 0.0 => Value::None,
+        Op::Div => Value::Float(a / b),
+        Op::Rem => Value::Float(a % b),
+        _ => Value::None,
+      }
+    }
+  }
+}
+
+fn apply(filter: &Filter, value: Value) -> Value {
+  match (filter, value) {
+    (Filter::Default(default), value) if value.is_empty() => default.clone(),
+    (Filter::Upper, Value::Str(value)) => Value::Str(value.to_uppercase()),
+    (Filter::Lower, Value::Str(value)) => Value::Str(value.to_lowercase()),
+    (Filter::Round, Value::Float(value)) => Value::Int(value.round() as i64),
+    (Filter::Floor, Value::Float(value)) => Value::Int(value.floor() as i64),
+    (Filter::Ceil, Value::Float(value)) => Value::Int(value.ceil() as i64),
+    (Filter::Abs, Value::Int(value)) => value.checked_abs().into(),
+    (Filter::Abs, Value::Float(value)) => Value::Float(value.abs()),
+    (Filter::Json, value) => Value::Str(value.to_json()),
+    (Filter::Date(format), Value::Int(timestamp)) => match DateTime::from_timestamp(timestamp, 0) {
+      Some(time) => {
+        let mut formatted = String::new();
+
+        match write!(formatted, "{}", time.with_timezone(&Local).format(format)) {
+          Ok(()) => Value::Str(formatted),
+          Err(_) => Value::None,
+        }
+      }
+      None => Value::None,
+    },
+    (_, value) => value,
+  }
+}
+
+fn format_spec(spec: &Spec, value: &Value) -> String {
+  let number = matches!(value, Value::Int(_) | Value::Float(_));
+
+  let text = match (spec.precision, value.as_f64()) {
+    (Some(precision), Some(float)) if number => format!("{float:.precision$}"),
+    _ => value.to_string(),
+  };
+
+  let padding = spec.width.saturating_sub(text.chars().count());
+
+  let default_align = if number { Align::Right } else { Align::Left };
+
+  let (before, after) = match spec.align.unwrap_or(default_align) {
+    Align::Left => (0, padding),
+    Align::Right => (padding, 0),
+    Align::Center => (padding / 2, padding - padding / 2),
+  };
+
+  let fill = spec.fill.to_string();
+
+  format!("{}{text}{}", fill.repeat(before), fill.repeat(after))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(source: &str) -> String {
+    Template::new(source).unwrap().render(&Context::new())
+  }
+
+  fn render_with(source: &str, reading: Option<i64>, state: &str) -> String {
+    let mut context = Context::new();
+    context.add("reading", reading);
+    context.add("state", state);
+    context.add("disconnected", state == "disconnected");
+    context.add("frozen", state == "frozen");
+
+    Template::new(source).unwrap().render(&context)
+  }
+
+  fn error(source: &str) -> String {
+    Template::new(source).unwrap_err().to_string()
+  }
+
+  #[test]
+  fn padding() {
+    assert_eq!(render_with("[{reading:>4}]", Some(72), ""), "[  72]");
+    assert_eq!(render_with("[{reading:0>4}]", Some(72), ""), "[0072]");
+    assert_eq!(render_with("[{reading:<4}]", Some(72), ""), "[72  ]");
+    assert_eq!(render_with("[{reading:*^6}]", Some(72), ""), "[**72**]");
+    assert_eq!(render_with("[{state:4}]", None, "on"), "[on  ]");
+    assert_eq!(render_with("[{reading:2}]", Some(123), ""), "[123]");
+    assert_eq!(render_with("{reading / 3:.2}", Some(100), ""), "33.33");
+    assert_eq!(render_with("{reading:06.1}", Some(72), ""), "  72.0");
+  }
+
+  #[test]
+  fn spec_limits() {
+    assert_eq!(render("[{1:>256}]").len(), 258);
+    assert!(error("{1:>257}").contains("width above 256"));
+    assert!(error("{1:>99999999999999999999999}").contains("width above"));
+    assert!(error("{1:.70000}").contains("precision above"));
+    assert!(error("{1:.}").contains("expected precision"));
+  }
+
+  #[test]
+  fn default_filter() {
+    assert_eq!(render_with("{reading|default:\"--\"}", None, ""), "--");
+    assert_eq!(render_with("{reading|default:\"--\"}", Some(72), ""), "72");
+    assert_eq!(render_with("{reading|default:0:>3}", None, ""), "  0");
+    assert!(error("{reading|default}").contains("filter needs a value"));
+    assert!(error("{reading|nope}").contains("unknown filter `nope`"));
+  }
+
+  #[test]
+  fn conditionals() {
+    let template = "{if frozen}~{elif disconnected}-{else}{reading}{end}";
+
+    assert_eq!(render_with(template, Some(72), "frozen"), "~");
+    assert_eq!(render_with(template, None, "disconnected"), "-");
+    assert_eq!(render_with(template, Some(72), "connected"), "72");
+
+    let nested = "{if reading}{if reading > 100}high{else}ok{end} {reading}{end}!";
+
+    assert_eq!(render_with(nested, Some(120), ""), "high 120!");
+    assert_eq!(render_with(nested, Some(60), ""), "ok 60!");
+    assert_eq!(render_with(nested, None, ""), "!");
+
+    assert_eq!(render_with("{if reading and not frozen}x{end}", Some(1), "frozen"), "");
+    assert_eq!(render_with("{if reading or frozen}x{end}", None, "frozen"), "x");
+
+    assert!(error("{if reading}x").contains("missing `{end}`"));
+    assert!(error("{if reading}{if frozen}x{end}").contains("missing `{end}`"));
+    assert!(error("x{end}").contains("`{end}` without `{if}`"));
+    assert!(error("{else}").contains("`{else}` without `{if}`"));
+    assert!(error("{if reading}{else}{elif frozen}{end}").contains("expected `{end}` after `{else}`"));
+  }
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(render("{1 + 2 * 3}"), "7");
+    assert_eq!(render("{(1 + 2) * 3}"), "9");
+    assert_eq!(render("{10 - 4 - 3}"), "3");
+    assert_eq!(render("{7 % 4 + 10 / 4}"), "5.5");
+    assert_eq!(render("{1 + 2 > 2 and 4 <= 2 * 2}"), "true");
+    assert_eq!(render("{\"a\" == \"a\"}"), "true");
+    assert_eq!(render_with("{reading * 2 + 1}", Some(40), ""), "81");
+    assert_eq!(render_with("{reading + 1}", None, ""), "");
+    assert!(error("{1 +}").contains("expected expression"));
+    assert!(error("{(1 + 2}").contains("expected `)`"));
+  }
+
+  #[test]
+  fn braces() {
+    assert_eq!(render("{{reading}}"), "{reading}");
+    assert_eq!(render("{{{1 + 1}}}"), "{2}");
+    assert!(error("a } b").contains("unmatched `}`"));
+    assert!(error("{reading").contains("expected `}`"));
+  }
+
+  #[test]
+  fn unknown_variables() {
+    let template = Template::new("{if frozen}{bpm}{end}{reading}").unwrap();
+
+    assert_eq!(template.variables(), BTreeSet::from(["bpm", "frozen", "reading"]));
+
+    let error = template.validate(VARIABLES, &[]).unwrap_err().to_string();
+
+    assert!(error.contains("unknown variable(s) bpm"), "{error}");
+
+    Template::new("{if frozen}~{end}{reading}")
+      .unwrap()
+      .validate(VARIABLES, &[])
+      .unwrap();
+  }
+
+  #[test]
+  fn negate() {
+    assert_eq!(render("{-(2 - 5)}"), "3");
+    assert_eq!(render("{-(1.5 * 2)}"), "-3");
+    assert_eq!(render("{-\"a\"|default:\"none\"}"), "none");
+  }
+
+  #[test]
+  fn integer_overflow_is_empty() {
+    assert_eq!(render("{-(-9223372036854775807 - 1)}"), "");
+    assert_eq!(render("{(-9223372036854775807 - 1)|abs}"), "");
+    assert_eq!(render("{9223372036854775807 + 1}"), "");
+  }
+
+  #[test]
+  fn average_windows() {
+    let windows = [Duration::from_secs(60), Duration::from_secs(300)];
+    let validate = |source: &str| Template::new(source).unwrap().validate(VARIABLES, &windows);
+
+    validate("{avg(60)} {if reading}{avg(300.0):.0}{end}").unwrap();
+
+    assert!(validate("{avg(30)}").unwrap_err().to_string().contains("avg(30)"));
+    assert!(validate("{bar(avg(120), 40, 200)}").is_err());
+    assert!(validate("{avg(reading)}").is_err());
+  }
+}
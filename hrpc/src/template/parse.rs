@@ -0,0 +1,645 @@
+use std::fmt::Display;
+
+use anyhow::anyhow;
+use chrono::format::{Item, StrftimeItems};
+
+use super::helpers;
+use super::value::Value;
+
+#[derive(Clone, Debug)]
+pub enum Node {
+  Text(String),
+  Output {
+    expr: Expr,
+    filters: Vec<Filter>,
+    spec: Option<Spec>,
+  },
+  If {
+    branches: Vec<(Expr, Vec<Node>)>,
+    otherwise: Vec<Node>,
+  },
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+  Literal(Value),
+  Variable(String),
+  Not(Box<Expr>),
+  Negate(Box<Expr>),
+  Binary(Box<Expr>, Op, Box<Expr>),
+  Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  And,
+  Or,
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+  Default(Value),
+  Upper,
+  Lower,
+  Round,
+  Floor,
+  Ceil,
+  Abs,
+  /// JSON literal, strings quoted and escaped
+  Json,
+  /// strftime format of a unix timestamp in local time
+  Date(String),
+}
+
+/// `[[fill]align][width][.precision]`, like `std::fmt`
+#[derive(Clone, Debug)]
+pub struct Spec {
+  pub fill: char,
+  pub align: Option<Align>,
+  pub width: usize,
+  pub precision: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Align {
+  Left,
+  Right,
+  Center,
+}
+
+enum Tag {
+  Node(Node),
+  If(Expr),
+  End(BlockEnd),
+}
+
+enum BlockEnd {
+  Elif(Expr),
+  Else,
+  End,
+}
+
+type Result<T> = anyhow::Result<T>;
+
+/// Widest `{value:>width}`
+const MAX_WIDTH: usize = 256;
+
+/// Most decimals of `{value:.precision}`, `format!` panics above `u16::MAX`
+const MAX_PRECISION: usize = 32;
+
+pub fn parse(source: &str) -> Result<Vec<Node>> {
+  let mut parser = Parser { source, pos: 0 };
+
+  match parser.block()? {
+    (nodes, None) => Ok(nodes),
+    (_, Some(BlockEnd::Elif(_))) => Err(parser.error("`{elif}` without `{if}`")),
+    (_, Some(BlockEnd::Else)) => Err(parser.error("`{else}` without `{if}`")),
+    (_, Some(BlockEnd::End)) => Err(parser.error("`{end}` without `{if}`")),
+  }
+}
+
+struct Parser<'a> {
+  source: &'a str,
+  pos: usize,
+}
+
+impl Parser<'_> {
+  fn rest(&self) -> &str {
+    &self.source[self.pos..]
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  fn eat(&mut self, s: &str) -> bool {
+    if self.rest().starts_with(s) {
+      self.pos += s.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.peek().is_some_and(char::is_whitespace) {
+      self.bump();
+    }
+  }
+
+  fn error(&self, message: impl Display) -> anyhow::Error {
+    anyhow!("{message} at position {} in template `{}`", self.pos, self.source)
+  }
+
+  /// Parses until the end of input or a tag closing the current block
+  fn block(&mut self) -> Result<(Vec<Node>, Option<BlockEnd>)> {
+    let mut nodes = vec![];
+    let mut text = String::new();
+
+    let end = loop {
+      if self.eat("{{") {
+        text.push('{');
+        continue;
+      }
+
+      if self.eat("}}") {
+        text.push('}');
+        continue;
+      }
+
+      let Some(c) = self.bump() else {
+        break None;
+      };
+
+      match c {
+        '{' => {
+          if !text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut text)));
+          }
+
+          match self.tag()? {
+            Tag::Node(node) => nodes.push(node),
+            Tag::If(condition) => nodes.push(self.conditional(condition)?),
+            Tag::End(end) => break Some(end),
+          }
+        }
+        '}' => return Err(self.error("unmatched `}`, use `}}` for a literal brace")),
+        c => text.push(c),
+      }
+    };
+
+    if !text.is_empty() {
+      nodes.push(Node::Text(text));
+    }
+
+    Ok((nodes, end))
+  }
+
+  fn conditional(&mut self, mut condition: Expr) -> Result<Node> {
+    let mut branches = vec![];
+
+    loop {
+      let (body, end) = self.block()?;
+      branches.push((condition, body));
+
+      match end {
+        Some(BlockEnd::Elif(next)) => condition = next,
+        Some(BlockEnd::Else) => {
+          let (otherwise, end) = self.block()?;
+
+          return match end {
+            Some(BlockEnd::End) => Ok(Node::If { branches, otherwise }),
+            _ => Err(self.error("expected `{end}` after `{else}`")),
+          };
+        }
+        Some(BlockEnd::End) => {
+          return Ok(Node::If {
+            branches,
+            otherwise: vec![],
+          })
+        }
+        None => return Err(self.error("missing `{end}`")),
+      }
+    }
+  }
+
+  /// Everything after a `{` up to and including the matching `}`
+  fn tag(&mut self) -> Result<Tag> {
+    self.skip_whitespace();
+
+    let start = self.pos;
+
+    let tag = match self.ident().as_deref() {
+      Some("if") => Tag::If(self.expression()?),
+      Some("elif") => Tag::End(BlockEnd::Elif(self.expression()?)),
+      Some("else") => Tag::End(BlockEnd::Else),
+      Some("end") => Tag::End(BlockEnd::End),
+      _ => {
+        self.pos = start;
+        Tag::Node(self.output()?)
+      }
+    };
+
+    self.skip_whitespace();
+
+    if !self.eat("}") {
+      return Err(self.error("expected `}`"));
+    }
+
+    Ok(tag)
+  }
+
+  fn output(&mut self) -> Result<Node> {
+    let expr = self.expression()?;
+    let mut filters = vec![];
+
+    loop {
+      self.skip_whitespace();
+
+      if !self.eat("|") {
+        break;
+      }
+
+      self.skip_whitespace();
+
+      let name = self.ident().ok_or_else(|| self.error("expected filter name"))?;
+      filters.push(self.filter(&name)?);
+    }
+
+    let spec = if self.eat(":") { Some(self.spec()?) } else { None };
+
+    Ok(Node::Output { expr, filters, spec })
+  }
+
+  fn filter(&mut self, name: &str) -> Result<Filter> {
+    let filter = match name {
+      "default" => Filter::Default(self.argument("default:\"--\"")?),
+      "date" => {
+        let Value::Str(format) = self.argument("date:\"%H:%M\"")? else {
+          return Err(self.error("`date` format must be a string"));
+        };
+
+        if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+          return Err(self.error(format!("invalid date format `{format}`")));
+        }
+
+        Filter::Date(format)
+      }
+      "upper" => Filter::Upper,
+      "lower" => Filter::Lower,
+      "round" => Filter::Round,
+      "floor" => Filter::Floor,
+      "ceil" => Filter::Ceil,
+      "abs" => Filter::Abs,
+      "json" => Filter::Json,
+      _ => return Err(self.error(format!("unknown filter `{name}`"))),
+    };
+
+    Ok(filter)
+  }
+
+  /// `:literal` after a filter name
+  fn argument(&mut self, example: &str) -> Result<Value> {
+    self.skip_whitespace();
+
+    if !self.eat(":") {
+      return Err(self.error(format!("filter needs a value, e.g. `{example}`")));
+    }
+
+    self.skip_whitespace();
+
+    self.literal()
+  }
+
+  fn spec(&mut self) -> Result<Spec> {
+    fn align(c: char) -> Option<Align> {
+      match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+      }
+    }
+
+    let mut spec = Spec {
+      fill: ' ',
+      align: None,
+      width: 0,
+      precision: None,
+    };
+
+    let mut chars = self.rest().chars();
+
+    match (chars.next(), chars.next()) {
+      (Some(fill), Some(c)) if fill != '}' && align(c).is_some() => {
+        spec.fill = fill;
+        spec.align = align(c);
+        self.bump();
+        self.bump();
+      }
+      (Some(c), _) if align(c).is_some() => {
+        spec.align = align(c);
+        self.bump();
+      }
+      _ => {}
+    }
+
+    spec.width = self.digits().unwrap_or(0);
+
+    if spec.width > MAX_WIDTH {
+      return Err(self.error(format!("width above {MAX_WIDTH}")));
+    }
+
+    if self.eat(".") {
+      let precision = self.digits().ok_or_else(|| self.error("expected precision"))?;
+
+      if precision > MAX_PRECISION {
+        return Err(self.error(format!("precision above {MAX_PRECISION}")));
+      }
+
+      spec.precision = Some(precision);
+    }
+
+    Ok(spec)
+  }
+
+  fn digits(&mut self) -> Option<usize> {
+    let start = self.pos;
+
+    while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+      self.bump();
+    }
+
+    if start == self.pos {
+      return None;
+    }
+
+    // too many digits for usize are still over any limit
+    Some(self.source[start..self.pos].parse().unwrap_or(usize::MAX))
+  }
+
+  fn ident(&mut self) -> Option<String> {
+    let start = self.pos;
+
+    if !self.peek().is_some_and(|c| c.is_alphabetic() || c == '_') {
+      return None;
+    }
+
+    while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+      self.bump();
+    }
+
+    Some(self.source[start..self.pos].to_string())
+  }
+
+  fn keyword(&mut self, keyword: &str) -> bool {
+    self.skip_whitespace();
+
+    let start = self.pos;
+
+    if self.ident().as_deref() == Some(keyword) {
+      return true;
+    }
+
+    self.pos = start;
+    false
+  }
+
+  fn expression(&mut self) -> Result<Expr> {
+    self.or()
+  }
+
+  fn or(&mut self) -> Result<Expr> {
+    let mut left = self.and()?;
+
+    while self.keyword("or") {
+      left = Expr::Binary(Box::new(left), Op::Or, Box::new(self.and()?));
+    }
+
+    Ok(left)
+  }
+
+  fn and(&mut self) -> Result<Expr> {
+    let mut left = self.not()?;
+
+    while self.keyword("and") {
+      left = Expr::Binary(Box::new(left), Op::And, Box::new(self.not()?));
+    }
+
+    Ok(left)
+  }
+
+  fn not(&mut self) -> Result<Expr> {
+    if self.keyword("not") {
+      return Ok(Expr::Not(Box::new(self.not()?)));
+    }
+
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expr> {
+    let left = self.additive()?;
+
+    self.skip_whitespace();
+
+    let op = if self.eat("==") {
+      Op::Eq
+    } else if self.eat("!=") {
+      Op::Ne
+    } else if self.eat("<=") {
+      Op::Le
+    } else if self.eat(">=") {
+      Op::Ge
+    } else if self.eat("<") {
+      Op::Lt
+    } else if self.eat(">") {
+      Op::Gt
+    } else {
+      return Ok(left);
+    };
+
+    Ok(Expr::Binary(Box::new(left), op, Box::new(self.additive()?)))
+  }
+
+  fn additive(&mut self) -> Result<Expr> {
+    let mut left = self.multiplicative()?;
+
+    loop {
+      self.skip_whitespace();
+
+      let op = if self.eat("+") {
+        Op::Add
+      } else if self.eat("-") {
+        Op::Sub
+      } else {
+        return Ok(left);
+      };
+
+      left = Expr::Binary(Box::new(left), op, Box::new(self.multiplicative()?));
+    }
+  }
+
+  fn multiplicative(&mut self) -> Result<Expr> {
+    let mut left = self.unary()?;
+
+    loop {
+      self.skip_whitespace();
+
+      let op = if self.eat("*") {
+        Op::Mul
+      } else if self.eat("/") {
+        Op::Div
+      } else if self.eat("%") {
+        Op::Rem
+      } else {
+        return Ok(left);
+      };
+
+      left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expr> {
+    self.skip_whitespace();
+
+    if self.eat("-") {
+      return Ok(Expr::Negate(Box::new(self.unary()?)));
+    }
+
+    self.primary()
+  }
+
+  fn primary(&mut self) -> Result<Expr> {
+    self.skip_whitespace();
+
+    match self.peek() {
+      Some('(') => {
+        self.bump();
+        let expr = self.expression()?;
+        self.skip_whitespace();
+
+        if !self.eat(")") {
+          return Err(self.error("expected `)`"));
+        }
+
+        Ok(expr)
+      }
+      Some(c) if c == '"' || c.is_ascii_digit() => Ok(Expr::Literal(self.literal()?)),
+      Some(c) if c.is_alphabetic() || c == '_' => {
+        let ident = self.ident().unwrap_or_default();
+
+        if self.peek() == Some('(') {
+          return self.call(ident);
+        }
+
+        Ok(match ident.as_str() {
+          "true" => Expr::Literal(Value::Bool(true)),
+          "false" => Expr::Literal(Value::Bool(false)),
+          "none" => Expr::Literal(Value::None),
+          _ => Expr::Variable(ident),
+        })
+      }
+      _ => Err(self.error("expected expression")),
+    }
+  }
+
+  fn call(&mut self, name: String) -> Result<Expr> {
+    self.bump();
+
+    let mut args = vec![];
+
+    self.skip_whitespace();
+
+    if !self.eat(")") {
+      loop {
+        args.push(self.expression()?);
+
+        self.skip_whitespace();
+
+        if self.eat(")") {
+          break;
+        }
+
+        if !self.eat(",") {
+          return Err(self.error("expected `,` or `)`"));
+        }
+      }
+    }
+
+    let Some(arity) = helpers::arity(&name) else {
+      return Err(self.error(format!("unknown function `{name}`")));
+    };
+
+    if !arity.contains(&args.len()) {
+      return Err(self.error(format!(
+        "`{name}` takes {} to {} arguments, got {}",
+        arity.start(),
+        arity.end(),
+        args.len()
+      )));
+    }
+
+    Ok(Expr::Call(name, args))
+  }
+
+  fn literal(&mut self) -> Result<Value> {
+    let negative = self.eat("-");
+
+    match self.peek() {
+      Some('"') if !negative => self.string().map(Value::Str),
+      Some(c) if c.is_ascii_digit() => {
+        let value = self.number()?;
+
+        Ok(match value {
+          Value::Int(value) if negative => Value::Int(-value),
+          Value::Float(value) if negative => Value::Float(-value),
+          value => value,
+        })
+      }
+      _ if !negative => match self.ident().as_deref() {
+        Some("true") => Ok(Value::Bool(true)),
+        Some("false") => Ok(Value::Bool(false)),
+        Some("none") => Ok(Value::None),
+        _ => Err(self.error("expected a string, number, `true`, `false` or `none`")),
+      },
+      _ => Err(self.error("expected a number")),
+    }
+  }
+
+  fn number(&mut self) -> Result<Value> {
+    let start = self.pos;
+
+    self.digits();
+
+    // a `.` is only a decimal point if followed by a digit
+    if self.rest().starts_with('.') && self.rest()[1..].starts_with(|c: char| c.is_ascii_digit()) {
+      self.bump();
+      self.digits();
+
+      let number = &self.source[start..self.pos];
+      return number.parse().map(Value::Float).map_err(|e| self.error(e));
+    }
+
+    let number = &self.source[start..self.pos];
+    number.parse().map(Value::Int).map_err(|e| self.error(e))
+  }
+
+  fn string(&mut self) -> Result<String> {
+    self.bump();
+
+    let mut string = String::new();
+
+    loop {
+      match self.bump() {
+        Some('"') => return Ok(string),
+        Some('\\') => match self.bump() {
+          Some('n') => string.push('\n'),
+          Some('t') => string.push('\t'),
+          Some(c) => string.push(c),
+          None => break,
+        },
+        Some(c) => string.push(c),
+        None => break,
+      }
+    }
+
+    Err(self.error("unterminated string"))
+  }
+}
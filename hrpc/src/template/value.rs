@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+  None,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Str(String),
+}
+
+impl Value {
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Value::None => false,
+      Value::Bool(value) => *value,
+      Value::Int(value) => *value != 0,
+      Value::Float(value) => *value != 0.0,
+      Value::Str(value) => !value.is_empty(),
+    }
+  }
+
+  /// `None` or an empty string
+  pub fn is_empty(&self) -> bool {
+    match self {
+      Value::None => true,
+      Value::Str(value) => value.is_empty(),
+      _ => false,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      Value::Int(value) => Some(*value as f64),
+      Value::Float(value) => Some(*value),
+      Value::Bool(value) => Some(*value as i64 as f64),
+      _ => None,
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    match self {
+      Value::None => "null".to_string(),
+      Value::Float(value) if !value.is_finite() => "null".to_string(),
+      Value::Str(value) => {
+        let mut json = String::with_capacity(value.len() + 2);
+
+        json.push('"');
+
+        for c in value.chars() {
+          match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+          }
+        }
+
+        json.push('"');
+
+        json
+      }
+      value => value.to_string(),
+    }
+  }
+
+  pub fn compare(&self, other: &Value) -> Option<Ordering> {
+    match (self, other) {
+      (Value::None, Value::None) => Some(Ordering::Equal),
+      (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+      (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+      (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+      (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+  }
+}
+
+impl Display for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Value::None => Ok(()),
+      Value::Bool(value) => write!(f, "{value}"),
+      Value::Int(value) => write!(f, "{value}"),
+      Value::Float(value) => write!(f, "{value}"),
+      Value::Str(value) => write!(f, "{value}"),
+    }
+  }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self {
+    Value::Bool(value)
+  }
+}
+
+impl From<u8> for Value {
+  fn from(value: u8) -> Self {
+    Value::Int(value as i64)
+  }
+}
+
+impl From<i64> for Value {
+  fn from(value: i64) -> Self {
+    Value::Int(value)
+  }
+}
+
+impl From<u64> for Value {
+  fn from(value: u64) -> Self {
+    Value::Int(value as i64)
+  }
+}
+
+impl From<f64> for Value {
+  fn from(value: f64) -> Self {
+    Value::Float(value)
+  }
+}
+
+impl From<String> for Value {
+  fn from(value: String) -> Self {
+    Value::Str(value)
+  }
+}
+
+impl From<&str> for Value {
+  fn from(value: &str) -> Self {
+    Value::Str(value.to_string())
+  }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+  fn from(value: Option<T>) -> Self {
+    value.map(Into::into).unwrap_or(Value::None)
+  }
+}
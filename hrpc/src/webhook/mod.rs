@@ -0,0 +1,168 @@
+//! Templated requests posted on readings, state or zone changes
+
+use reqwest::Client;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use crate::config::{Config, WebhookConfig, WebhookTrigger};
+use crate::event::{self, EventKind};
+use crate::metrics::{self, Sink};
+use crate::outbox::{self, Outbox};
+use crate::{reading, shutdown, template};
+
+pub fn webhook_thread(config: Config) {
+  tokio::task::block_in_place(|| {
+    let rt = Runtime::new().unwrap();
+
+    rt.block_on(webhook_tasks(config));
+  })
+}
+
+/// One task per enabled `[[webhook]]` entry
+async fn webhook_tasks(config: Config) {
+  let mut tasks = JoinSet::new();
+
+  for webhook in config.webhook.into_iter().filter(|webhook| webhook.enable) {
+    tasks.spawn(async move {
+      if let Err(e) = webhook_task(&webhook).await {
+        error!("webhook_task `{}` error: {}", webhook.url, e);
+        metrics::error(Sink::Webhook);
+      }
+    });
+  }
+
+  while tasks.join_next().await.is_some() {}
+}
+
+async fn webhook_task(webhook: &WebhookConfig) -> anyhow::Result<()> {
+  debug!("webhook_task `{}` start", webhook.url);
+
+  let client = Client::builder().timeout(webhook.delivery.timeout).build()?;
+
+  let mut events = event::subscribe();
+  let mut outbox = Outbox::new(&webhook.delivery);
+  let mut state = reading::get().state();
+
+  loop {
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(event) => {
+          let triggered = match event.kind {
+            EventKind::Reading(reading) => {
+              let changed = reading.state() != state;
+              state = reading.state();
+
+              match webhook.on {
+                WebhookTrigger::Reading => Some(reading),
+                WebhookTrigger::State => changed.then_some(reading),
+                WebhookTrigger::Zone => None,
+              }
+            }
+            EventKind::Zone { .. } => (webhook.on == WebhookTrigger::Zone).then(reading::get),
+            _ => None,
+          };
+
+          if let Some(reading) = triggered {
+            // rendered now so queued requests keep their values
+            outbox.push(webhook.template.render(&template::context_at(&reading, event.time)));
+          }
+        }
+        Err(RecvError::Lagged(missed)) => warn!("webhook_task `{}` missed {} events", webhook.url, missed),
+        Err(RecvError::Closed) => break,
+      },
+      _ = outbox::wait(outbox.next_attempt()) => deliver(&client, webhook, &mut outbox).await,
+      _ = shutdown::wait() => {
+        // one last attempt for whatever is queued
+        let _ = timeout(webhook.delivery.timeout, deliver(&client, webhook, &mut outbox)).await;
+
+        break;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Posts queued bodies in order until empty or a request fails
+async fn deliver(client: &Client, webhook: &WebhookConfig, outbox: &mut Outbox<String>) {
+  while let Some(body) = outbox.first().cloned() {
+    let mut request = client
+      .post(&webhook.url)
+      .header("Content-Type", &webhook.content_type)
+      .body(body);
+
+    for (name, value) in &webhook.headers {
+      request = request.header(name, value);
+    }
+
+    match request.send().await.and_then(|response| response.error_for_status()) {
+      Ok(_) => outbox.sent(1),
+      Err(e) => {
+        let delay = outbox.failed();
+
+        warn!(
+          "webhook `{}` failed: {}, retrying in {}ms",
+          webhook.url,
+          e,
+          delay.as_millis()
+        );
+        metrics::error(Sink::Webhook);
+
+        break;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::outbox::stand_in::StandIn;
+
+  fn webhook(url: &str, buffer: usize) -> WebhookConfig {
+    toml::from_str(&format!(
+      "enable = true\nurl = \"{url}\"\ntemplate = \"{{reading}}\"\nbuffer = {buffer}"
+    ))
+    .unwrap()
+  }
+
+  #[tokio::test]
+  async fn delivers_in_order_after_reconnecting() {
+    let mut stand_in = StandIn::start().await;
+    let webhook = webhook(&stand_in.url, 3);
+
+    let client = Client::new();
+    let mut outbox = Outbox::new(&webhook.delivery);
+
+    outbox.push("70".to_string());
+    deliver(&client, &webhook, &mut outbox).await;
+
+    assert!(outbox.is_empty());
+    assert_eq!(stand_in.received(), ["70"]);
+
+    stand_in.stop().await;
+
+    for body in ["71", "72"] {
+      outbox.push(body.to_string());
+    }
+
+    deliver(&client, &webhook, &mut outbox).await;
+
+    // kept for the retry, which backs off
+    assert_eq!(outbox.first().map(String::as_str), Some("71"));
+    assert!(!outbox.is_due());
+
+    // the oldest dropped past the buffer
+    for body in ["73", "74"] {
+      outbox.push(body.to_string());
+    }
+
+    stand_in.restart().await;
+    deliver(&client, &webhook, &mut outbox).await;
+
+    assert!(outbox.is_empty());
+    assert_eq!(stand_in.received(), ["72", "73", "74"]);
+  }
+}
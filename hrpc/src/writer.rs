@@ -0,0 +1,151 @@
+//! Persistent file handles for the log and file sinks, reopened when the file
+//! is deleted or replaced
+
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter};
+
+/// Appends through a buffer that is written out by [`AppendWriter::flush`]
+/// or once full
+pub struct AppendWriter {
+  path: PathBuf,
+  writer: Option<BufWriter<File>>,
+  /// file size including the buffer
+  len: u64,
+}
+
+impl AppendWriter {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      writer: None,
+      len: 0,
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Opens the file if needed
+  pub async fn len(&mut self) -> anyhow::Result<u64> {
+    self.open().await?;
+
+    Ok(self.len)
+  }
+
+  pub async fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+    self.open().await?.write_all(data).await?;
+    self.len += data.len() as u64;
+
+    Ok(())
+  }
+
+  pub async fn flush(&mut self) -> anyhow::Result<()> {
+    let Some(writer) = &mut self.writer else {
+      return Ok(());
+    };
+
+    if replaced(writer.get_ref(), &self.path).await {
+      warn!("`{}` was moved or deleted, reopening", self.path.display());
+
+      let pending = writer.buffer().to_vec();
+
+      self.writer = None;
+      self.write(&pending).await?;
+    }
+
+    if let Some(writer) = &mut self.writer {
+      writer.flush().await?;
+    }
+
+    Ok(())
+  }
+
+  /// Flushes and releases the handle, the next write reopens the file
+  pub async fn close(&mut self) -> anyhow::Result<()> {
+    self.flush().await?;
+    self.writer = None;
+
+    Ok(())
+  }
+
+  async fn open(&mut self) -> anyhow::Result<&mut BufWriter<File>> {
+    if self.writer.is_none() {
+      let file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+
+      self.len = file.metadata().await?.len();
+      self.writer = Some(BufWriter::new(file));
+    }
+
+    Ok(self.writer.as_mut().unwrap())
+  }
+}
+
+/// Replaces the whole content on every write through one handle
+pub struct OverwriteWriter {
+  path: PathBuf,
+  file: Option<File>,
+}
+
+impl OverwriteWriter {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      file: None,
+    }
+  }
+
+  pub async fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(file) = &self.file {
+      if replaced(file, &self.path).await {
+        warn!("`{}` was moved or deleted, reopening", self.path.display());
+
+        self.file = None;
+      }
+    }
+
+    if self.file.is_none() {
+      self.file = Some(
+        OpenOptions::new()
+          .create(true)
+          .write(true)
+          .truncate(true)
+          .open(&self.path)
+          .await?,
+      );
+    }
+
+    let file = self.file.as_mut().unwrap();
+
+    file.seek(SeekFrom::Start(0)).await?;
+    file.write_all(data).await?;
+    file.set_len(data.len() as u64).await?;
+    file.flush().await?;
+
+    Ok(())
+  }
+}
+
+/// `path` is gone or no longer the open `file`
+async fn replaced(file: &File, path: &Path) -> bool {
+  let Ok(on_disk) = tokio::fs::metadata(path).await else {
+    return true;
+  };
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+
+    if let Ok(open) = file.metadata().await {
+      return (open.dev(), open.ino()) != (on_disk.dev(), on_disk.ino());
+    }
+  }
+
+  #[cfg(not(unix))]
+  let _ = (file, on_disk);
+
+  false
+}
@@ -0,0 +1,145 @@
+//! Heart rate zone of the current reading
+
+use std::sync::Mutex;
+
+use crate::config::ZonesConfig;
+use crate::event::{self, EventKind};
+use crate::reading::Reading;
+
+static ZONE: Mutex<Option<Zone>> = Mutex::new(None);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Zone {
+  /// 1 for the first configured zone
+  pub index: usize,
+  pub name: String,
+}
+
+/// `None` while disconnected or below the first zone
+pub fn get() -> Option<Zone> {
+  ZONE.lock().unwrap().clone()
+}
+
+/// Moves to the zone of `reading` and emits [`EventKind::Zone`] if it
+/// changed. Frozen readings keep the zone, no reading leaves it
+pub fn update(config: &ZonesConfig, reading: Reading) {
+  let mut zone = ZONE.lock().unwrap();
+
+  let index = match reading {
+    Reading::None => None,
+    Reading::Frozen(_) => return,
+    Reading::Value(value) => next(config, zone.as_ref().map(|zone| zone.index), value),
+  };
+
+  if zone.as_ref().map(|zone| zone.index) == index {
+    return;
+  }
+
+  let to = index.map(|index| Zone {
+    index,
+    name: config.zone[index - 1].name.clone(),
+  });
+
+  let from = std::mem::replace(&mut *zone, to.clone());
+
+  drop(zone);
+
+  debug!(
+    "zone {} -> {}",
+    from.as_ref().map_or("none", |zone| &zone.name),
+    to.as_ref().map_or("none", |zone| &zone.name)
+  );
+
+  event::emit(EventKind::Zone { from, to });
+}
+
+/// Stays in `current` until `bpm` is more than the hysteresis past its bounds
+fn next(config: &ZonesConfig, current: Option<usize>, bpm: u8) -> Option<usize> {
+  let bounds = config.bounds();
+
+  let zone = bounds.iter().rposition(|min| bpm >= *min).map(|index| index + 1);
+
+  let Some(current) = current.filter(|current| *current <= bounds.len()) else {
+    return zone;
+  };
+
+  let hysteresis = config.hysteresis as i32;
+  let bpm = bpm as i32;
+
+  let above_min = bpm >= bounds[current - 1] as i32 - hysteresis;
+  let below_max = bounds.get(current).is_none_or(|max| bpm < *max as i32 + hysteresis);
+
+  if above_min && below_max {
+    Some(current)
+  } else {
+    zone
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::ZoneConfig;
+
+  fn zones(max_hr: Option<u8>, zones: &[(Option<u8>, Option<f32>)]) -> ZonesConfig {
+    ZonesConfig {
+      max_hr,
+      hysteresis: 2,
+      zone: zones
+        .iter()
+        .enumerate()
+        .map(|(index, (min, min_percent))| ZoneConfig {
+          name: format!("zone {}", index + 1),
+          min: *min,
+          min_percent: *min_percent,
+        })
+        .collect(),
+    }
+  }
+
+  /// Zone after each reading, starting from none
+  fn walk(config: &ZonesConfig, readings: &[u8]) -> Vec<Option<usize>> {
+    readings
+      .iter()
+      .scan(None, |current, bpm| {
+        *current = next(config, *current, *bpm);
+        Some(*current)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn hysteresis() {
+    let config = zones(None, &[(Some(100), None), (Some(120), None)]);
+
+    // flapping around 120 stays in zone 1 until 2 past the bound
+    assert_eq!(
+      walk(&config, &[110, 120, 119, 121, 120, 121, 122]),
+      [Some(1), Some(1), Some(1), Some(1), Some(1), Some(1), Some(2)]
+    );
+
+    // and back down only 2 below it
+    assert_eq!(
+      walk(&config, &[125, 119, 120, 118, 121, 117]),
+      [Some(2), Some(2), Some(2), Some(2), Some(2), Some(1)]
+    );
+
+    // below the first zone is none, with the same margin
+    assert_eq!(walk(&config, &[100, 99, 98, 97]), [Some(1), Some(1), Some(1), None]);
+  }
+
+  #[test]
+  fn percent_bounds() {
+    let config = zones(
+      Some(190),
+      &[(None, Some(50.0)), (None, Some(60.5)), (Some(150), Some(90.0))],
+    );
+
+    // rounded, an explicit `min` wins
+    assert_eq!(config.bounds(), [95, 115, 150]);
+    assert_eq!(walk(&config, &[95, 117, 152]), [Some(1), Some(2), Some(3)]);
+
+    // without `max_hr` percent zones start at 0
+    assert_eq!(zones(None, &[(None, Some(50.0))]).bounds(), [0]);
+  }
+}
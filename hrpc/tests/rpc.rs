@@ -0,0 +1,153 @@
+//! Discord presence against a fake ipc server, in its own process since it
+//! sets `XDG_RUNTIME_DIR` and the global reading
+
+#![cfg(unix)]
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hrpc::config::Config;
+use hrpc::reading::{self, Reading};
+use hrpc::rpc::{self, rpc_thread, Status};
+use hrpc::shutdown;
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn config() -> Config {
+  let mut config: Config = toml::from_str(include_str!("../../config.example.toml")).unwrap();
+
+  config.rpc.enable = true;
+  config.rpc.update_interval = Duration::ZERO;
+  config.rpc.min_interval = Duration::ZERO;
+  config.rpc.reconnect_delay = Duration::from_millis(50);
+  config.rpc.reconnect_delay_max = Duration::from_millis(200);
+
+  config
+}
+
+fn accept(listener: &UnixListener) -> UnixStream {
+  let deadline = Instant::now() + TIMEOUT;
+
+  loop {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        stream.set_nonblocking(false).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        return stream;
+      }
+      Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+        thread::sleep(Duration::from_millis(10));
+      }
+      Err(e) => panic!("no connection: {e}"),
+    }
+  }
+}
+
+/// `(opcode, payload)`, `None` once the client closed the socket
+fn read_frame(stream: &mut UnixStream) -> Option<(u32, Value)> {
+  let mut header = [0; 8];
+  stream.read_exact(&mut header).ok()?;
+
+  let op = u32::from_le_bytes(header[..4].try_into().unwrap());
+  let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+  let mut payload = vec![0; len as usize];
+  stream.read_exact(&mut payload).unwrap();
+
+  Some((op, serde_json::from_slice(&payload).unwrap()))
+}
+
+fn write_frame(stream: &mut UnixStream, op: u32, payload: Value) -> std::io::Result<()> {
+  let payload = payload.to_string();
+
+  stream.write_all(&op.to_le_bytes())?;
+  stream.write_all(&(payload.len() as u32).to_le_bytes())?;
+  stream.write_all(payload.as_bytes())
+}
+
+/// Answers the handshake and returns the first activity set
+fn handshake(stream: &mut UnixStream) -> Value {
+  let (op, handshake) = read_frame(stream).unwrap();
+
+  assert_eq!(op, 0);
+  assert_eq!(handshake["client_id"], "000000000000000000");
+
+  write_frame(stream, 1, json!({ "cmd": "DISPATCH", "evt": "READY" })).unwrap();
+
+  next_activity(stream).expect("no activity after the handshake")
+}
+
+/// `args.activity` of the next SET_ACTIVITY, `None` once closed
+fn next_activity(stream: &mut UnixStream) -> Option<Value> {
+  loop {
+    let (op, mut payload) = read_frame(stream)?;
+
+    if op == 1 && payload["cmd"] == "SET_ACTIVITY" {
+      // the client may already be gone after the clear on shutdown
+      let _ = write_frame(stream, 1, json!({ "cmd": "SET_ACTIVITY", "nonce": payload["nonce"] }));
+
+      return Some(payload["args"]["activity"].take());
+    }
+
+    if op == 2 {
+      return None;
+    }
+  }
+}
+
+fn wait_for(status: Status) {
+  let deadline = Instant::now() + TIMEOUT;
+
+  while rpc::status() != status {
+    assert!(Instant::now() < deadline, "status stayed {}", rpc::status());
+
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+#[test]
+fn reconnects_and_clears() {
+  let dir = std::env::temp_dir().join(format!("hrpc-rpc-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  std::env::set_var("XDG_RUNTIME_DIR", &dir);
+
+  reading::set(Reading::Value(70));
+
+  let rpc = thread::spawn(|| rpc_thread(config()));
+
+  // discord isn't running yet
+  wait_for(Status::Waiting);
+  thread::sleep(Duration::from_millis(300));
+
+  let listener = UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+  listener.set_nonblocking(true).unwrap();
+
+  let mut stream = accept(&listener);
+  let activity = handshake(&mut stream);
+
+  assert_eq!(activity["state"], "70 bpm");
+  wait_for(Status::Connected);
+
+  // discord restarts, the next update fails and reconnects
+  drop(stream);
+  reading::set(Reading::Value(80));
+
+  let mut stream = accept(&listener);
+  let activity = handshake(&mut stream);
+
+  assert_eq!(activity["state"], "80 bpm");
+  wait_for(Status::Connected);
+
+  shutdown::trigger();
+
+  assert_eq!(next_activity(&mut stream), Some(Value::Null));
+  assert_eq!(next_activity(&mut stream), None);
+
+  rpc.join().unwrap();
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
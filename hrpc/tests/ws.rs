@@ -0,0 +1,199 @@
+//! `/api/ws` against a local client
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use hrpc::config::ServerConfig;
+use hrpc::event::{self, EventKind};
+use hrpc::reading::{self, Reading};
+use hrpc::{profile, server};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// `host:port` of a new server
+async fn serve() -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let address = listener.local_addr().unwrap();
+
+  tokio::spawn(server::serve(listener, ServerConfig::default()));
+
+  address.to_string()
+}
+
+async fn connect() -> Socket {
+  let (socket, _) = connect_async(format!("ws://{}/api/ws", serve().await)).await.unwrap();
+
+  socket
+}
+
+async fn send(socket: &mut Socket, request: Value) {
+  socket.send(Message::Text(request.to_string())).await.unwrap();
+}
+
+async fn next(socket: &mut Socket) -> Value {
+  loop {
+    let message = timeout(Duration::from_secs(5), socket.next())
+      .await
+      .expect("no message within 5s")
+      .unwrap()
+      .unwrap();
+
+    if let Message::Text(text) = message {
+      return serde_json::from_str(&text).unwrap();
+    }
+  }
+}
+
+/// Next `count` messages, in whatever order the server sent them
+async fn next_n(socket: &mut Socket, count: usize) -> Vec<Value> {
+  let mut messages = Vec::new();
+
+  for _ in 0..count {
+    messages.push(next(socket).await);
+  }
+
+  messages
+}
+
+fn find<'a>(messages: &'a [Value], kind: &str) -> &'a Value {
+  messages
+    .iter()
+    .find(|message| message["type"] == kind)
+    .unwrap_or_else(|| panic!("no `{kind}` in {messages:?}"))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn websocket_api() {
+  profile::init(&["default".to_string(), "stream".to_string()]);
+  reading::set(Reading::None);
+
+  let mut socket = connect().await;
+
+  let current = next(&mut socket).await;
+
+  assert_eq!(current["type"], "current");
+  assert_eq!(current["state"], "disconnected");
+  assert_eq!(current["profile"], "default");
+
+  send(
+    &mut socket,
+    json!({ "type": "subscribe", "id": 1, "events": ["reading", "state", "profile"] }),
+  )
+  .await;
+
+  let subscribed = next(&mut socket).await;
+
+  assert_eq!(subscribed["type"], "subscribed");
+  assert_eq!(subscribed["id"], 1);
+  assert_eq!(subscribed["events"], json!(["profile", "reading", "state"]));
+
+  reading::set(Reading::Value(72));
+  event::emit(EventKind::Reading(Reading::Value(72)));
+
+  let reading = next(&mut socket).await;
+
+  assert_eq!(reading["event"], "reading");
+  assert_eq!(reading["bpm"], 72);
+  assert_eq!(reading["state"], "connected");
+
+  let state = next(&mut socket).await;
+
+  assert_eq!(state["event"], "state");
+  assert_eq!(state["from"], "disconnected");
+  assert_eq!(state["to"], "connected");
+
+  // same state, only the reading is pushed
+  event::emit(EventKind::Reading(Reading::Value(74)));
+  event::emit(EventKind::Mark { label: None });
+  event::emit(EventKind::Reading(Reading::Frozen(74)));
+
+  let messages = next_n(&mut socket, 3).await;
+
+  assert_eq!(messages[0]["bpm"], 74);
+  assert_eq!(messages[1]["event"], "reading");
+  assert_eq!(messages[1]["state"], "frozen");
+  assert_eq!(messages[2]["event"], "state");
+
+  send(&mut socket, json!({ "type": "mark", "id": "a", "label": "start" })).await;
+  assert_eq!(next(&mut socket).await, json!({ "type": "ok", "id": "a" }));
+
+  send(
+    &mut socket,
+    json!({ "type": "mark", "id": "b", "label": "x".repeat(101) }),
+  )
+  .await;
+  assert_eq!(next(&mut socket).await["type"], "error");
+
+  send(&mut socket, json!({ "type": "profile", "id": 2, "name": "stream" })).await;
+
+  let messages = next_n(&mut socket, 2).await;
+
+  assert_eq!(find(&messages, "ok")["id"], 2);
+  assert_eq!(find(&messages, "event")["event"], "profile");
+  assert_eq!(find(&messages, "event")["name"], "stream");
+  assert_eq!(profile::get().as_deref(), Some("stream"));
+
+  send(&mut socket, json!({ "type": "profile", "id": 3, "name": "unknown" })).await;
+
+  let error = next(&mut socket).await;
+
+  assert_eq!(error["type"], "error");
+  assert_eq!(error["id"], 3);
+  assert_eq!(profile::get().as_deref(), Some("stream"));
+
+  send(
+    &mut socket,
+    json!({ "type": "unsubscribe", "id": 4, "events": ["reading", "state"] }),
+  )
+  .await;
+  assert_eq!(next(&mut socket).await["events"], json!(["profile"]));
+
+  send(&mut socket, json!({ "type": "subscribe", "events": ["nope"] })).await;
+  assert_eq!(next(&mut socket).await["type"], "error");
+
+  send(&mut socket, json!({ "type": "stats", "id": 5 })).await;
+
+  let stats = next(&mut socket).await;
+
+  assert_eq!(stats["type"], "stats");
+  assert_eq!(stats["id"], 5);
+
+  send(&mut socket, json!({ "type": "current", "id": 6 })).await;
+
+  let current = next(&mut socket).await;
+
+  assert_eq!(current["id"], 6);
+  assert_eq!(current["bpm"], 72);
+  assert_eq!(current["profile"], "stream");
+
+  socket.send(Message::text("not json")).await.unwrap();
+  assert_eq!(next(&mut socket).await["type"], "error");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cross_origin() {
+  let address = serve().await;
+
+  let connect = |origin: &str| {
+    let mut request = format!("ws://{address}/api/ws").into_client_request().unwrap();
+    request.headers_mut().insert("Origin", origin.parse().unwrap());
+
+    connect_async(request)
+  };
+
+  connect(&format!("http://{address}")).await.unwrap();
+
+  for origin in ["http://example.com", "null"] {
+    let Err(Error::Http(response)) = connect(origin).await else {
+      panic!("websocket from {origin} accepted");
+    };
+
+    assert_eq!(response.status(), 403);
+  }
+}
@@ -1,8 +1,9 @@
 use std::time::{Duration, Instant};
 
 use eframe::NativeOptions;
-use egui::CentralPanel;
+use egui::{CentralPanel, Color32};
 use hrpc::reading::{self, Reading};
+use hrpc::{alert, rpc, session, stats};
 
 use crate::graph::Graph;
 
@@ -39,6 +40,46 @@ impl eframe::App for App {
       }
 
       ui.label(format!("reading: {}", self.current_reading));
+      ui.label(format!("discord: {}", rpc::status()));
+
+      if let Some(session) = session::get() {
+        let value = |value: Option<u8>| value.map_or("-".to_string(), |value| value.to_string());
+        let average = |average: Option<f64>| average.map_or("-".to_string(), |average| format!("{average:.1}"));
+
+        ui.label(format!(
+          "session: {}, min {} avg {} max {}",
+          stats::clock(session.duration()),
+          value(session.min),
+          average(session.average()),
+          value(session.max)
+        ));
+
+        for (window, rolling) in session.averages() {
+          ui.label(format!("avg {}s: {}", window.as_secs(), average(rolling)));
+        }
+
+        if let (Some(trend), Some(rate)) = (session.trend(), session.trend_rate()) {
+          ui.label(format!("trend: {} ({rate:+.1} bpm/min)", trend.name()));
+        }
+
+        let zones = session.zone_times();
+
+        if !zones.is_empty() {
+          ui.label(format!("zones: {}", stats::zone_times(&zones)));
+        }
+      }
+
+      for notification in alert::notifications() {
+        ui.colored_label(
+          Color32::LIGHT_RED,
+          format!(
+            "{} {}: {}",
+            notification.time.format("%H:%M:%S"),
+            notification.alert,
+            notification.message
+          ),
+        );
+      }
 
       self.graph.show(ui, 200.0);
     });
@@ -4,8 +4,11 @@
 use std::{env, thread};
 
 use anyhow::{anyhow, Context};
+use hrpc::alert::alert_thread;
 use hrpc::config::load_config;
 use hrpc::monitor::monitor_thread;
+use hrpc::rpc::rpc_thread;
+use hrpc::{profile, shutdown};
 use hrpc_gui::app;
 
 #[macro_use]
@@ -22,11 +25,13 @@ fn main() -> anyhow::Result<()> {
 
   let config = load_config().context("failed to load config from `config.toml`")?;
 
+  profile::init(&config.profiles);
+
   // let osc_config = config.clone();
   // let osc = thread::spawn(move || osc_thread(osc_config));
 
-  // let rpc_config = config.clone();
-  // let rpc = thread::spawn(move || rpc_thread(rpc_config));
+  let rpc_config = config.clone();
+  let rpc = thread::spawn(move || rpc_thread(rpc_config));
 
   // let file_config = config.clone();
   // let file = thread::spawn(move || file_thread(file_config));
@@ -34,10 +39,18 @@ fn main() -> anyhow::Result<()> {
   // let log_config = config.clone();
   // let log = thread::spawn(move || log_thread(log_config));
 
+  let alert_config = config.clone();
+  let alert = thread::spawn(move || alert_thread(alert_config));
+
   let monitor = thread::spawn(move || monitor_thread(config));
 
   app::start().map_err(|err| anyhow!("{err}"))?;
 
+  shutdown::trigger();
+
+  rpc.join().unwrap();
+  alert.join().unwrap();
+
   drop(monitor);
 
   Ok(())